@@ -0,0 +1,46 @@
+use clap::ArgMatches;
+use failure::{format_err, Error};
+
+use crate::jocker::attach;
+use crate::jocker::container::ContainerError;
+use crate::jocker::state::ContainerStatus;
+use crate::jocker::tty::RawMode;
+use crate::jocker::Config;
+
+pub fn attach(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let container_id = matches.value_of("CONTAINER").unwrap();
+    let detach_keys = attach::parse_detach_keys(matches.value_of("detach-keys").unwrap())?;
+
+    let container_store = config.container_store();
+    let container = container_store
+        .get_container(container_id)
+        .ok_or_else(|| format_err!("no such container {}", container_id))?;
+    if container.config().state().status() != ContainerStatus::Running {
+        return Err(ContainerError::NotRunning.into());
+    }
+
+    // Keys are passed untouched to the pseudo-terminal of the container, which interprets them
+    let raw_mode = if container.config().tty() {
+        RawMode::enable(0)?
+    } else {
+        None
+    };
+    let detached = attach::attach(&container.attach_socket_path(), &detach_keys);
+    drop(raw_mode);
+
+    if detached? {
+        println!("detached from {}", container_id);
+        return Ok(());
+    }
+
+    // The container has exited, report its status like `run` does
+    let exit_code = container_store
+        .get_container(container_id)
+        .and_then(|container| container.config().state().exit_code());
+    match exit_code {
+        Some(exit_code) if exit_code != 0 => {
+            Err(ContainerError::CommandExitedWithError(exit_code).into())
+        }
+        _ => Ok(()),
+    }
+}
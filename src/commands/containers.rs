@@ -1,13 +1,169 @@
+use std::io::Write;
+use std::time::Duration;
+
 use clap::ArgMatches;
-use failure::Error;
+use failure::{format_err, Error};
+use nix::sys::signal::Signal;
 
+use super::options;
+use crate::jocker::logs::{self, LogEntry, LogReader, LogStream};
+use crate::jocker::state::{now, ContainerState, ContainerStatus};
 use crate::jocker::Config;
 
-pub fn list(config: &Config, _matches: &ArgMatches) -> Result<(), Error> {
+/// Format a duration in seconds the way humans would say it
+fn format_duration(seconds: u64) -> String {
+    let (value, unit) = match seconds {
+        0..=59 => (seconds, "second"),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    format!("{} {}{}", value, unit, if value == 1 { "" } else { "s" })
+}
+
+/// Format a time in seconds since the epoch relatively to the current time
+fn format_time_ago(time: Option<u64>) -> String {
+    match time {
+        Some(time) => format!("{} ago", format_duration(now().saturating_sub(time))),
+        None => "-".to_string(),
+    }
+}
+
+/// Describe the state of a container like `docker ps` does
+fn format_status(state: &ContainerState) -> String {
+    match state.status() {
+        ContainerStatus::Created => "Created".to_string(),
+        ContainerStatus::Running => format!(
+            "Up {}",
+            format_duration(now().saturating_sub(state.started_at().unwrap_or_else(now)))
+        ),
+        ContainerStatus::Exited => {
+            let mut status = match state.exit_code() {
+                Some(exit_code) => format!("Exited ({})", exit_code),
+                None => "Exited".to_string(),
+            };
+            status.push(' ');
+            status.push_str(&format_time_ago(state.finished_at()));
+            if state.oom_killed() {
+                status.push_str(" (out of memory)");
+            }
+            if state.pids_limit_reached() {
+                status.push_str(" (process limit reached)");
+            }
+            status
+        }
+        ContainerStatus::Dead => "Dead".to_string(),
+    }
+}
+
+pub fn list(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
     let container_store = config.container_store();
 
+    if matches.is_present("quiet") {
+        for container in container_store.containers()? {
+            println!("{}", container?.name());
+        }
+        return Ok(());
+    }
+
+    let mut rows = vec![[
+        "CONTAINER ID".to_string(),
+        "IMAGE".to_string(),
+        "CREATED".to_string(),
+        "STATUS".to_string(),
+    ]];
     for container in container_store.containers()? {
-        println!("{}", container?.name());
+        let container = container?;
+        let container_config = container.config();
+        let state = container_config.state();
+
+        rows.push([
+            container.name().to_string(),
+            container_config.image_name().to_string(),
+            format_time_ago(state.created_at()),
+            format_status(state),
+        ]);
+    }
+
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in &rows {
+        println!(
+            "{:<w0$}   {:<w1$}   {:<w2$}   {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+
+    Ok(())
+}
+
+/// Print a log entry to the stream it was written to
+fn print_log_entry(entry: &LogEntry, timestamps: bool) {
+    let line = if timestamps {
+        format!("{} {}", entry.time(), entry.log())
+    } else {
+        entry.log().to_string()
+    };
+
+    let _ = match entry.stream() {
+        LogStream::Stdout => std::io::stdout().write_all(line.as_bytes()),
+        LogStream::Stderr => std::io::stderr().write_all(line.as_bytes()),
+    };
+}
+
+pub fn logs(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let container_name = matches.value_of("CONTAINER").unwrap();
+    let container_store = config.container_store();
+    let container = container_store
+        .get_container(container_name)
+        .ok_or_else(|| format_err!("no such container {}", container_name))?;
+
+    let since = matches
+        .value_of("since")
+        .map(logs::parse_since)
+        .transpose()?;
+    let tail = match matches.value_of("tail") {
+        Some("all") | None => None,
+        Some(tail) => Some(
+            tail.parse::<usize>()
+                .map_err(|_| format_err!("invalid number of lines {}", tail))?,
+        ),
+    };
+    let timestamps = matches.is_present("timestamps");
+    let is_shown = |entry: &LogEntry| since.map_or(true, |since| entry.is_after(since));
+
+    let reader = LogReader::new(container.log_path());
+    let (entries, offset) = reader.entries()?;
+    let entries: Vec<_> = entries.into_iter().filter(is_shown).collect();
+    let skipped = tail.map_or(0, |tail| entries.len().saturating_sub(tail));
+    for entry in &entries[skipped..] {
+        print_log_entry(entry, timestamps);
+    }
+
+    if matches.is_present("follow") {
+        let is_running = || {
+            container_store
+                .get_container(container_name)
+                .map_or(false, |container| {
+                    container.config().state().status() == ContainerStatus::Running
+                })
+        };
+        reader.follow(offset, is_running, |entry| {
+            if is_shown(&entry) {
+                print_log_entry(&entry, timestamps);
+            }
+        })?;
     }
 
     Ok(())
@@ -20,6 +176,10 @@ pub fn remove(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
         let container = container_store.get_container(container_name);
 
         if let Some(container) = container {
+            if container.config().state().status() == ContainerStatus::Running {
+                println!("unable to remove {}: container is running", container_name);
+                continue;
+            }
             container_store.remove_container(container)?;
             println!("{}: removed", container_name);
         } else {
@@ -30,22 +190,76 @@ pub fn remove(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
+/// Parse a signal given by number or by name, with or without the `SIG` prefix
+fn parse_signal(s: &str) -> Result<Signal, Error> {
+    let signal = match s.parse() {
+        Ok(number) => Signal::from_c_int(number).ok(),
+        Err(_) => {
+            let name = s.to_ascii_uppercase();
+            if name.starts_with("SIG") {
+                name.parse().ok()
+            } else {
+                format!("SIG{}", name).parse().ok()
+            }
+        }
+    };
+
+    signal.ok_or_else(|| format_err!("invalid signal {}", s))
+}
+
+pub fn stop(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let container_store = config.container_store();
+    let timeout = matches.value_of("time").unwrap();
+    let timeout = Duration::from_secs(
+        timeout
+            .parse()
+            .map_err(|_| format_err!("invalid timeout {}", timeout))?,
+    );
+
+    for container_name in matches.values_of("CONTAINER").unwrap() {
+        if let Some(mut container) = container_store.get_container(container_name) {
+            container.stop(timeout)?;
+            println!("{}: stopped", container_name);
+        } else {
+            println!("unable to stop {}: no such container", container_name);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn kill(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let container_store = config.container_store();
+    let signal = parse_signal(matches.value_of("signal").unwrap())?;
+
+    for container_name in matches.values_of("CONTAINER").unwrap() {
+        if let Some(container) = container_store.get_container(container_name) {
+            match container.kill(signal) {
+                Ok(()) => println!("{}: killed", container_name),
+                Err(e) => println!("unable to kill {}: {}", container_name, e),
+            }
+        } else {
+            println!("unable to kill {}: no such container", container_name);
+        }
+    }
+
+    Ok(())
+}
+
 pub fn start(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
     let container_id = matches.value_of("CONTAINER").unwrap();
 
     println!("Loading container with ID {}", container_id);
     let container_store = config.container_store();
-    let container = container_store.get_container(&container_id).unwrap();
+    let mut container = container_store.get_container(&container_id).unwrap();
+    options::apply_container_options(container.config_mut(), matches)?;
+    container.save_config()?;
 
     println!("Running container with ID {}", container_id);
-    let mut cmd_args = Vec::new();
-    cmd_args.push(matches.value_of("COMMAND").unwrap());
-    if let Some(args) = matches.values_of("ARG") {
-        cmd_args.extend(args);
-    }
-    let cmd = cmd_args.join(" ");
+    let image_config = container.image_config(config)?;
+    let command = options::image_command(matches, image_config.execution_config())?;
 
-    container.run_command(config, &cmd)?;
+    container.run_command(config, &command)?;
 
     Ok(())
 }
@@ -0,0 +1,25 @@
+use clap::ArgMatches;
+use failure::{format_err, Error};
+
+use super::options;
+use crate::jocker::Config;
+
+pub fn exec(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let container_id = matches.value_of("CONTAINER").unwrap();
+
+    let container_store = config.container_store();
+    let mut container = container_store
+        .get_container(container_id)
+        .ok_or_else(|| format_err!("no such container {}", container_id))?;
+    // The options only apply to this command, so the configuration is not saved
+    options::apply_process_options(container.config_mut(), matches)?;
+
+    let command = options::command(matches);
+    container.exec_command(
+        &command,
+        matches.is_present("interactive"),
+        matches.is_present("tty"),
+    )?;
+
+    Ok(())
+}
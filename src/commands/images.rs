@@ -4,24 +4,163 @@ use std::path::Path;
 use clap::ArgMatches;
 use failure::{format_err, Error, Fail, ResultExt};
 
-use crate::jocker::container::{Container, ContainerError};
-use crate::jocker::image::ImageError;
+use crate::jocker::container::{shell_command, Container, ContainerError};
+use crate::jocker::docker;
+use crate::jocker::image::{ExecutionConfig, ImageError};
+use crate::jocker::layer;
+use crate::jocker::network::NetworkMode;
+use crate::jocker::oci;
+use crate::jocker::resources::Resources;
 use crate::jocker::Config;
 
+use super::options;
+
+/// Default maximum number of processes in build steps, so that fork bombs cannot exhaust the host
+const DEFAULT_BUILD_PIDS_LIMIT: u64 = 1024;
+
 /// Enumeration for the type of commands allowed in Jockerfiles
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 enum JockerfileCommand {
+    /// Run a command line through the shell of the container
     Run(String),
+    /// Run a program with the given arguments, written as a JSON array
+    RunExec(Vec<String>),
+    /// Set the default command of the image
+    Cmd(Vec<String>),
+    /// Set the program the command of the containers is passed to
+    Entrypoint(Vec<String>),
+    /// Set `KEY=VALUE` environment variables
+    Env(Vec<String>),
+    /// Set the working directory, relative to the previous one
+    Workdir(String),
+    /// Set the `USER[:GROUP]` the processes run as
+    User(String),
+    /// Set labels of the image
+    Label(Vec<(String, String)>),
+    /// Declare ports the processes listen on, such as `80/tcp`
+    Expose(Vec<String>),
+    /// Declare paths where the containers store data outside of the image
+    Volume(Vec<String>),
+}
+
+impl JockerfileCommand {
+    /// Apply the command to the configuration of an image, which the commands running in a
+    /// container leave untouched
+    fn apply(&self, execution_config: &mut ExecutionConfig) {
+        match self {
+            JockerfileCommand::Run(_) | JockerfileCommand::RunExec(_) => {}
+            JockerfileCommand::Cmd(cmd) => execution_config.set_cmd(Some(cmd.clone())),
+            JockerfileCommand::Entrypoint(entrypoint) => {
+                execution_config.set_entrypoint(Some(entrypoint.clone()))
+            }
+            JockerfileCommand::Env(variables) => {
+                for variable in variables {
+                    execution_config.set_env(variable);
+                }
+            }
+            JockerfileCommand::Workdir(path) => {
+                let working_dir =
+                    Path::new(execution_config.working_dir().unwrap_or("/")).join(path);
+                execution_config.set_working_dir(Some(working_dir.display().to_string()));
+            }
+            JockerfileCommand::User(user) => execution_config.set_user(Some(user.clone())),
+            JockerfileCommand::Label(labels) => {
+                for (key, value) in labels {
+                    execution_config.set_label(key.clone(), value.clone());
+                }
+            }
+            JockerfileCommand::Expose(ports) => {
+                for port in ports {
+                    execution_config.add_exposed_port(port.clone());
+                }
+            }
+            JockerfileCommand::Volume(paths) => {
+                for path in paths {
+                    execution_config.add_volume(path.clone());
+                }
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for JockerfileCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let json = |args: &[String]| serde_json::to_string(args).map_err(|_| std::fmt::Error);
+
         match &self {
             JockerfileCommand::Run(args) => f.write_fmt(format_args!("RUN {}", args)),
+            JockerfileCommand::RunExec(args) => f.write_fmt(format_args!("RUN {}", json(args)?)),
+            JockerfileCommand::Cmd(args) => f.write_fmt(format_args!("CMD {}", json(args)?)),
+            JockerfileCommand::Entrypoint(args) => {
+                f.write_fmt(format_args!("ENTRYPOINT {}", json(args)?))
+            }
+            JockerfileCommand::Env(variables) => {
+                f.write_fmt(format_args!("ENV {}", variables.join(" ")))
+            }
+            JockerfileCommand::Workdir(path) => f.write_fmt(format_args!("WORKDIR {}", path)),
+            JockerfileCommand::User(user) => f.write_fmt(format_args!("USER {}", user)),
+            JockerfileCommand::Label(labels) => {
+                f.write_str("LABEL")?;
+                for (key, value) in labels {
+                    f.write_fmt(format_args!(" {}={:?}", key, value))?;
+                }
+                Ok(())
+            }
+            JockerfileCommand::Expose(ports) => {
+                f.write_fmt(format_args!("EXPOSE {}", ports.join(" ")))
+            }
+            JockerfileCommand::Volume(paths) => {
+                f.write_fmt(format_args!("VOLUME {}", json(paths)?))
+            }
         }
     }
 }
 
+/// Parse the arguments of a command given either as a JSON array or as a command line, run
+/// through the shell of the container like in Dockerfiles
+fn parse_exec_args(args: &str) -> Result<Vec<String>, ImageBuildError> {
+    match serde_json::from_str::<Vec<String>>(args) {
+        Ok(ref exec_args) if exec_args.is_empty() => Err(ImageBuildError::InvalidArguments(1, 0)),
+        Ok(exec_args) => Ok(exec_args),
+        Err(_) => Ok(shell_command(args)),
+    }
+}
+
+/// Parse `KEY=VALUE` pairs separated by whitespace, whose values may be double-quoted
+fn parse_assignments(args: &str) -> Result<Vec<(String, String)>, ImageBuildError> {
+    args.split_ascii_whitespace()
+        .map(|assignment| {
+            let mut pieces = assignment.splitn(2, '=');
+            match (pieces.next(), pieces.next()) {
+                (Some(key), Some(value)) if !key.is_empty() => {
+                    let value =
+                        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                            &value[1..value.len() - 1]
+                        } else {
+                            value
+                        };
+                    Ok((key.to_string(), value.to_string()))
+                }
+                _ => Err(ImageBuildError::InvalidAssignment(assignment.to_string())),
+            }
+        })
+        .collect()
+}
+
+/// Parse a port such as `80` or `53/udp`, using TCP by default
+fn parse_port(port: &str) -> Result<String, ImageBuildError> {
+    let mut pieces = port.splitn(2, '/');
+    let number = pieces.next().and_then(|number| number.parse::<u16>().ok());
+    let protocol = pieces.next().unwrap_or("tcp");
+
+    match (number, protocol) {
+        (Some(number), "tcp") | (Some(number), "udp") | (Some(number), "sctp") => {
+            Ok(format!("{}/{}", number, protocol))
+        }
+        _ => Err(ImageBuildError::InvalidPort(port.to_string())),
+    }
+}
+
 /// Error type describing errors related to image building
 #[derive(Fail, Debug)]
 enum ImageBuildError {
@@ -45,10 +184,22 @@ enum ImageBuildError {
     #[fail(display = "invalid arguments, expected {}, got {}", _0, _1)]
     InvalidArguments(u32, u32),
 
+    /// The build script contained an assignment which is not of the form `KEY=VALUE`
+    #[fail(display = "invalid assignment {}, expected KEY=VALUE", _0)]
+    InvalidAssignment(String),
+
+    /// The build script contained an invalid port
+    #[fail(display = "invalid port {}", _0)]
+    InvalidPort(String),
+
     /// An intermediate container produced an error
     #[fail(display = "error in intermediate container: {}", _0)]
     IntermediateContainerError(ContainerError),
 
+    /// The configuration of an intermediate image could not be changed
+    #[fail(display = "unable to update the image configuration: {}", _0)]
+    CannotUpdateImage(ImageError),
+
     /// The resulting image could not be created
     #[fail(display = "unable to create the resulting image: {}", _0)]
     CannotCreateResultingImage(ImageError),
@@ -57,12 +208,19 @@ enum ImageBuildError {
 /// Structure representing an image builder, which allows building jocker images
 struct ImageBuilder<T: BufRead> {
     reader: T,
+    resources: Resources,
+    keep_intermediates: bool,
 }
 
 impl<T: BufRead> ImageBuilder<T> {
-    /// Create an [`ImageBuilder`] from a reader
-    pub fn from_reader(reader: T) -> Self {
-        Self { reader }
+    /// Create an [`ImageBuilder`] from a reader, running build steps with the given resource
+    /// limits and keeping their containers and images if `keep_intermediates` is set
+    pub fn from_reader(reader: T, resources: Resources, keep_intermediates: bool) -> Self {
+        Self {
+            reader,
+            resources,
+            keep_intermediates,
+        }
     }
 
     fn parse_from_directive<'a>(
@@ -84,67 +242,222 @@ impl<T: BufRead> ImageBuilder<T> {
 
     fn parse_command(line: &str) -> Result<JockerfileCommand, ImageBuildError> {
         let mut pieces = line.splitn(2, ' ');
+        let command = pieces.next().unwrap();
+        let args = pieces.next().map_or("", str::trim);
 
-        match pieces.next() {
-            Some("RUN") => match pieces.next() {
-                Some(args) if !args.is_empty() => Ok(JockerfileCommand::Run(args.to_string())),
-                _ => Err(ImageBuildError::InvalidArguments(1, 0)),
+        match command {
+            "RUN" | "CMD" | "ENTRYPOINT" | "ENV" | "WORKDIR" | "USER" | "LABEL" | "EXPOSE"
+            | "VOLUME"
+                if args.is_empty() =>
+            {
+                Err(ImageBuildError::InvalidArguments(1, 0))
+            }
+            // Like in Dockerfiles, anything that is not a JSON array is a command line
+            "RUN" => match serde_json::from_str::<Vec<String>>(args) {
+                Ok(ref exec_args) if exec_args.is_empty() => {
+                    Err(ImageBuildError::InvalidArguments(1, 0))
+                }
+                Ok(exec_args) => Ok(JockerfileCommand::RunExec(exec_args)),
+                Err(_) => Ok(JockerfileCommand::Run(args.to_string())),
             },
-            Some(cmd) => Err(ImageBuildError::InvalidCommand(cmd.to_string())),
-            _ => unreachable!(),
+            "CMD" => Ok(JockerfileCommand::Cmd(parse_exec_args(args)?)),
+            "ENTRYPOINT" => Ok(JockerfileCommand::Entrypoint(parse_exec_args(args)?)),
+            "ENV" => {
+                // The older `ENV KEY VALUE` form sets a single variable, whose value may have
+                // spaces
+                let mut pieces = args.splitn(2, char::is_whitespace);
+                let variables = match (pieces.next(), pieces.next()) {
+                    (Some(key), Some(value)) if !key.contains('=') => {
+                        vec![format!("{}={}", key, value.trim())]
+                    }
+                    _ => parse_assignments(args)?
+                        .into_iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect(),
+                };
+                Ok(JockerfileCommand::Env(variables))
+            }
+            "WORKDIR" => Ok(JockerfileCommand::Workdir(args.to_string())),
+            "USER" => Ok(JockerfileCommand::User(args.to_string())),
+            "LABEL" => Ok(JockerfileCommand::Label(parse_assignments(args)?)),
+            "EXPOSE" => Ok(JockerfileCommand::Expose(
+                args.split_ascii_whitespace()
+                    .map(parse_port)
+                    .collect::<Result<_, _>>()?,
+            )),
+            "VOLUME" => Ok(JockerfileCommand::Volume(
+                serde_json::from_str(args)
+                    .unwrap_or_else(|_| args.split_ascii_whitespace().map(String::from).collect()),
+            )),
+            cmd => Err(ImageBuildError::InvalidCommand(cmd.to_string())),
         }
     }
 
+    /// Run the command line of a build step in its container
     fn execute_command(
         config: &Config,
-        container: &Container,
+        container: &mut Container,
         command: &JockerfileCommand,
+        args: &[String],
     ) -> Result<(), ImageBuildError> {
         println!("Running \"{}\"...", command);
 
-        match command {
-            JockerfileCommand::Run(args) => container
-                .run_command(config, &args)
-                .map_err(ImageBuildError::IntermediateContainerError),
-        }
+        container
+            .run_command(config, args)
+            .map_err(ImageBuildError::IntermediateContainerError)
     }
 
-    /// Build the image
-    pub fn build(self, config: &Config, name: Option<String>) -> Result<(), ImageBuildError> {
+    /// Run every build step in a container created from the image of the previous step, and
+    /// return the name of the image of the last step
+    ///
+    /// The names of the images created by the steps are added to `temporary_images`.
+    fn run_steps(
+        &self,
+        config: &Config,
+        lines: &[String],
+        temporary_images: &mut Vec<String>,
+    ) -> Result<String, ImageBuildError> {
         let container_store = config.container_store();
-
-        let lines = self.reader.lines().collect::<Result<Vec<_>, _>>().unwrap();
+        let image_store = config.image_store();
         let mut lines_iter = lines.iter().filter(|s| !s.is_empty());
 
         let mut base_image = Self::parse_from_directive(&mut lines_iter)?;
 
         for line in lines_iter {
-            let container = container_store
-                .create_container(uuid::Uuid::new_v4().to_string(), base_image)
-                .map_err(ImageBuildError::IntermediateContainerError)?;
-
             let command = Self::parse_command(line)?;
-            Self::execute_command(config, &container, &command)?;
 
+            let image: Result<_, ImageError> = try {
+                let image = image_store
+                    .get_image(&base_image)
+                    .ok_or(ImageError::InvalidImage)?;
+                let image_digest = image.manifest_digest()?;
+                let manifest = image_store.manifest_from_digest(&image_digest)?;
+                let image_config = image_store.config(&manifest)?;
+                (image_digest, manifest, image_config)
+            };
+            let (image_digest, manifest, mut image_config) = image
+                .map_err(ContainerError::InitializationError)
+                .map_err(ImageBuildError::IntermediateContainerError)?;
             let image_name = uuid::Uuid::new_v4().to_string();
-            println!("Saving temporary container to image {}...", &image_name);
-            container
-                .export_as_image(config, image_name.clone())
-                .expect("cannot export");
+
+            let args = match &command {
+                JockerfileCommand::Run(args) => shell_command(args),
+                JockerfileCommand::RunExec(args) => args.clone(),
+                // The commands which only change the configuration do not need a container
+                _ => {
+                    println!("Applying \"{}\"...", command);
+                    command.apply(image_config.execution_config_mut());
+                    image_store
+                        .create_image(
+                            image_name.clone(),
+                            &image_config,
+                            manifest.layers().to_vec(),
+                        )
+                        .map_err(ImageBuildError::CannotUpdateImage)?;
+                    temporary_images.push(image_name.clone());
+                    base_image = image_name;
+                    continue;
+                }
+            };
+
+            let mut container = container_store
+                .create_container(uuid::Uuid::new_v4().to_string(), base_image, image_digest)
+                .map_err(ImageBuildError::IntermediateContainerError)?;
+
+            let step_result: Result<(), ImageBuildError> = try {
+                container
+                    .config_mut()
+                    .apply_image_defaults(image_config.execution_config());
+                // Build steps keep using the network of the host
+                container.config_mut().set_network_mode(NetworkMode::Host);
+                *container.config_mut().resources_mut() = self.resources.clone();
+                container
+                    .save_config()
+                    .map_err(ImageBuildError::IntermediateContainerError)?;
+
+                Self::execute_command(config, &mut container, &command, &args)?;
+
+                println!("Saving temporary container to image {}...", &image_name);
+                container
+                    .export_as_image(config, image_name.clone())
+                    .map_err(ImageBuildError::IntermediateContainerError)?;
+                temporary_images.push(image_name.clone());
+            };
+
+            if !self.keep_intermediates {
+                let container_name = container.name().to_string();
+                if let Err(e) = container_store.remove_container(container) {
+                    eprintln!(
+                        "warning: cannot remove intermediate container {}: {}",
+                        container_name, e
+                    );
+                }
+            }
+            step_result?;
             base_image = image_name;
         }
 
-        if let Some(name) = name {
-            let image_store = config.image_store();
-            let image = image_store
-                .get_image(&base_image)
-                .expect("cannot find the built image");
-            image_store
-                .copy_image(name, &image)
-                .map_err(ImageBuildError::CannotCreateResultingImage)?;
+        Ok(base_image)
+    }
+
+    /// Remove an image created by a build step
+    fn remove_temporary_image(config: &Config, image_name: &str) {
+        let image_store = config.image_store();
+
+        if let Some(image) = image_store.get_image(image_name) {
+            let result: Result<(), Error> = try {
+                let used_manifests = config.container_store().image_digests()?;
+                image_store.remove_image(image, &used_manifests)?;
+            };
+            if let Err(e) = result {
+                eprintln!(
+                    "warning: cannot remove intermediate image {}: {}",
+                    image_name, e
+                );
+            }
         }
+    }
 
-        Ok(())
+    /// Build the image
+    ///
+    /// Unless intermediates are kept, the containers and images of the build steps are removed
+    /// whether the build succeeds or not, except for the image of the last step when it is the
+    /// only result of an unnamed build.
+    pub fn build(mut self, config: &Config, name: Option<String>) -> Result<(), ImageBuildError> {
+        let lines = self
+            .reader
+            .by_ref()
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut temporary_images = Vec::new();
+
+        let build_result: Result<(), ImageBuildError> = try {
+            let built_image = self.run_steps(config, &lines, &mut temporary_images)?;
+
+            match &name {
+                Some(name) => {
+                    let image_store = config.image_store();
+                    let image = image_store
+                        .get_image(&built_image)
+                        .expect("cannot find the built image");
+                    image_store
+                        .copy_image(name.clone(), &image)
+                        .map_err(ImageBuildError::CannotCreateResultingImage)?;
+                }
+                None => {
+                    temporary_images.retain(|image_name| *image_name != built_image);
+                }
+            }
+        };
+
+        if !self.keep_intermediates {
+            for image_name in &temporary_images {
+                Self::remove_temporary_image(config, image_name);
+            }
+        }
+
+        build_result
     }
 }
 
@@ -157,7 +470,15 @@ pub fn build(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
     })?;
     let file = BufReader::new(file);
 
-    let builder = ImageBuilder::from_reader(file);
+    let mut resources = Resources::default();
+    let pids_limit = match matches.value_of("pids-limit") {
+        Some(pids_limit) => options::pids_limit_option(pids_limit)?,
+        None => Some(DEFAULT_BUILD_PIDS_LIMIT),
+    };
+    resources.set_pids_limit(pids_limit)?;
+
+    let builder =
+        ImageBuilder::from_reader(file, resources, matches.is_present("keep-intermediates"));
     builder
         .build(config, matches.value_of("name").map(String::from))
         .with_context(|_| format_err!("cannot build image"))?;
@@ -165,12 +486,74 @@ pub fn build(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
+/// Detect the format of an image to import, which is an image layout if it is a directory, and
+/// otherwise a tarball holding an archive created by `docker save`, an image layout or a root
+/// filesystem
+fn detect_format(path: &Path) -> &'static str {
+    if path.is_dir() {
+        return "oci";
+    }
+
+    // Recent versions of Docker save archives which are also image layouts, imported as docker
+    // archives to keep the tags of their manifest
+    let scan_result: Result<Option<&'static str>, std::io::Error> = try {
+        let reader = layer::decompress(std::fs::File::open(path)?)?;
+        let mut format = None;
+        for entry in tar::Archive::new(reader).entries()? {
+            let entry = entry?;
+            let entry_path = entry.path()?;
+            let entry_path = entry_path.strip_prefix(".").unwrap_or(&entry_path);
+            if entry_path == Path::new(docker::MANIFEST_FILE) {
+                format = Some("docker");
+                break;
+            } else if entry_path == Path::new(oci::LAYOUT_FILE) {
+                format = Some("oci");
+            }
+        }
+        format
+    };
+
+    scan_result.ok().flatten().unwrap_or("tar")
+}
+
 pub fn import(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
     let name = matches.value_of("NAME").unwrap();
     let path = Path::new(matches.value_of("PATH").unwrap());
     let image_store = config.image_store();
 
-    image_store.import_image(name.to_string(), path)?;
+    let format = matches
+        .value_of("format")
+        .unwrap_or_else(|| detect_format(path));
+    match format {
+        "docker" => {
+            let names = docker::import_archive(&image_store, name.to_string(), path)
+                .with_context(|_| format_err!("cannot import image {}", name))?;
+            for name in names {
+                println!("{}: imported", name);
+            }
+        }
+        "oci" => {
+            oci::import_layout(&image_store, name.to_string(), path)
+                .with_context(|_| format_err!("cannot import image {}", name))?;
+        }
+        _ => {
+            image_store.import_image(name.to_string(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn export(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let image_name = matches.value_of("IMAGE").unwrap();
+    let path = Path::new(matches.value_of("OUT").unwrap());
+    let image_store = config.image_store();
+    let image = image_store
+        .get_image(image_name)
+        .ok_or_else(|| format_err!("no such image {}", image_name))?;
+
+    oci::export_layout(&image_store, &image, path)
+        .with_context(|_| format_err!("cannot export image {}", image_name))?;
 
     Ok(())
 }
@@ -180,12 +563,12 @@ pub fn list(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
 
     if matches.is_present("quiet") {
         for image in image_store.images()? {
-            println!("{}", image?.name().display());
+            println!("{}", image?.name());
         }
     } else {
         for image in image_store.images()? {
             let image = image?;
-            println!("{}: {}", image.name().display(), image.path().display());
+            println!("{}: {}", image.name(), image.path().display());
         }
     }
 
@@ -194,10 +577,29 @@ pub fn list(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
 
 pub fn remove(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
     let image_store = config.image_store();
+    let container_store = config.container_store();
 
     for image_name in matches.values_of("IMAGE").unwrap() {
+        // The layers of an image are needed by the containers created from it
+        let user = container_store
+            .containers()
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .find(|container| container.config().image_name() == image_name);
+        if let Some(container) = user {
+            println!(
+                "unable to remove {}: image is used by container {}",
+                image_name,
+                container.name()
+            );
+            continue;
+        }
+
         if let Some(image) = image_store.get_image(image_name) {
-            image_store.remove_image(image)?;
+            // The containers keep using the blobs of the images they were created from, even
+            // after the names of these images are given to other ones
+            image_store.remove_image(image, &container_store.image_digests()?)?;
             println!("{}: removed", image_name);
         } else {
             println!("unable to remove {}: no such image", image_name);
@@ -0,0 +1,31 @@
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use nix::unistd::{Gid, Uid};
+
+use crate::jocker::init::{self, Credentials};
+
+pub fn init(matches: &ArgMatches) -> Result<(), Error> {
+    let uid = matches.value_of("uid").unwrap().parse()?;
+    let gid = matches.value_of("gid").unwrap().parse()?;
+    let groups = match matches.value_of("groups") {
+        Some("") => Some(Vec::new()),
+        Some(groups) => Some(
+            groups
+                .split(',')
+                .map(|group| group.parse().map(Gid::from_raw))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format_err!("invalid groups: {}", groups))?,
+        ),
+        None => None,
+    };
+    let credentials = Credentials::new(Uid::from_raw(uid), Gid::from_raw(gid), groups);
+
+    let command: Vec<String> = matches
+        .values_of("COMMAND")
+        .unwrap()
+        .map(String::from)
+        .collect();
+
+    let exit_code = init::run(&command, &credentials)?;
+    std::process::exit(exit_code);
+}
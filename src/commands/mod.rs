@@ -1,5 +1,13 @@
+mod attach;
 pub mod containers;
+mod exec;
 pub mod images;
+mod init;
+mod options;
 mod run;
+pub mod volumes;
 
+pub use self::attach::attach;
+pub use self::exec::exec;
+pub use self::init::init;
 pub use self::run::run;
@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use failure::{format_err, Error, ResultExt};
+
+use crate::jocker::container::{shell_command, ContainerConfig};
+use crate::jocker::image::ExecutionConfig;
+use crate::jocker::logs::LogConfig;
+use crate::jocker::resources::{parse_size, DeviceRate, Resources, ResourcesError};
+use crate::jocker::volume::BindMount;
+
+/// Build the command to run in a container from the `COMMAND` argument, the program followed by
+/// its arguments
+pub fn command(matches: &ArgMatches) -> Vec<String> {
+    let mut values = matches.values_of("COMMAND").unwrap().map(String::from);
+    let command = values.next().unwrap();
+    let args = values;
+
+    if matches.is_present("shell") {
+        // Make the arguments available to the command line as positional parameters
+        let mut shell_args = shell_command(&command);
+        shell_args.push("/bin/sh".to_string());
+        shell_args.extend(args);
+        shell_args
+    } else {
+        std::iter::once(command).chain(args).collect()
+    }
+}
+
+/// Build the command to run in a container created from an image, whose entrypoint is followed
+/// by the `COMMAND` argument if it is given, and by its default command otherwise
+pub fn image_command(
+    matches: &ArgMatches,
+    defaults: &ExecutionConfig,
+) -> Result<Vec<String>, Error> {
+    let args = if matches.is_present("COMMAND") {
+        command(matches)
+    } else {
+        defaults.cmd().to_vec()
+    };
+    let command: Vec<_> = defaults.entrypoint().iter().cloned().chain(args).collect();
+
+    if command.is_empty() {
+        Err(format_err!(
+            "no command given, and the image has no default command"
+        ))
+    } else {
+        Ok(command)
+    }
+}
+
+/// Normalize an environment variable given as `KEY=VALUE`, or as `KEY` to take its value from
+/// the current environment
+fn environment_variable(variable: &str) -> Option<String> {
+    if variable.contains('=') {
+        Some(variable.to_string())
+    } else {
+        std::env::var(variable)
+            .ok()
+            .map(|value| format!("{}={}", variable, value))
+    }
+}
+
+/// Apply the options configuring a container (`-e`, `--env-file`, `-w`, `-u` and `-v`)
+pub fn apply_container_options(
+    container_config: &mut ContainerConfig,
+    matches: &ArgMatches,
+) -> Result<(), Error> {
+    apply_process_options(container_config, matches)?;
+
+    for volume in matches.values_of("volume").into_iter().flatten() {
+        container_config.add_mount(volume.parse::<BindMount>()?);
+    }
+
+    Ok(())
+}
+
+/// Apply the options configuring the processes of a container (`-e`, `--env-file`, `-w` and
+/// `-u`), which are the only ones applying to commands run in a running container
+pub fn apply_process_options(
+    container_config: &mut ContainerConfig,
+    matches: &ArgMatches,
+) -> Result<(), Error> {
+    for path in matches.values_of("env-file").into_iter().flatten() {
+        let content = fs::read_to_string(path)
+            .with_context(|_| format_err!("cannot read environment file {}", path))?;
+        let variables = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(environment_variable);
+
+        for variable in variables {
+            container_config.set_env(&variable);
+        }
+    }
+
+    for variable in matches.values_of("env").into_iter().flatten() {
+        if let Some(variable) = environment_variable(variable) {
+            container_config.set_env(&variable);
+        }
+    }
+
+    if let Some(working_dir) = matches.value_of("workdir") {
+        let working_dir = PathBuf::from(working_dir);
+        if !working_dir.is_absolute() {
+            return Err(format_err!(
+                "the working directory must be an absolute path"
+            ));
+        }
+        container_config.set_working_dir(Some(working_dir));
+    }
+
+    if let Some(user) = matches.value_of("user") {
+        container_config.set_user(Some(user.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Apply the options configuring the log of a container (`--log-max-size`, `--log-max-files`)
+pub fn apply_log_options(log_config: &mut LogConfig, matches: &ArgMatches) -> Result<(), Error> {
+    if let Some(max_size) = matches.value_of("log-max-size") {
+        log_config.set_max_size(parse_size(max_size)?);
+    }
+
+    if let Some(max_files) = matches.value_of("log-max-files") {
+        match max_files.parse() {
+            Ok(max_files) if max_files > 0 => log_config.set_max_files(max_files),
+            _ => return Err(format_err!("invalid number of log files {}", max_files)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a pids limit, where -1 means that the number of processes is not limited
+pub fn pids_limit_option(pids_limit: &str) -> Result<Option<u64>, ResourcesError> {
+    match pids_limit {
+        "-1" => Ok(None),
+        _ => pids_limit
+            .parse()
+            .map(Some)
+            .map_err(|_| ResourcesError::InvalidValue("pids-limit", pids_limit.to_string())),
+    }
+}
+
+/// Apply the options limiting the resources of a container
+pub fn apply_resource_options(
+    resources: &mut Resources,
+    matches: &ArgMatches,
+) -> Result<(), Error> {
+    let memory = matches.value_of("memory").map(parse_size).transpose()?;
+    let memory_swap = match matches.value_of("memory-swap") {
+        // Like in Docker, -1 means that the swap is not limited
+        Some("-1") | None => None,
+        Some(memory_swap) => Some(parse_size(memory_swap)?),
+    };
+    resources.set_memory(memory, memory_swap)?;
+
+    let swappiness = matches
+        .value_of("memory-swappiness")
+        .map(|swappiness| {
+            swappiness.parse().map_err(|_| {
+                ResourcesError::InvalidValue("memory-swappiness", swappiness.to_string())
+            })
+        })
+        .transpose()?;
+    resources.set_memory_swappiness(swappiness)?;
+
+    let cpu_shares = matches
+        .value_of("cpu-shares")
+        .map(|cpu_shares| {
+            cpu_shares
+                .parse()
+                .map_err(|_| ResourcesError::InvalidValue("cpu-shares", cpu_shares.to_string()))
+        })
+        .transpose()?;
+    resources.set_cpu_shares(cpu_shares)?;
+
+    let cpus = matches
+        .value_of("cpus")
+        .map(|cpus| {
+            cpus.parse()
+                .map_err(|_| ResourcesError::InvalidValue("cpus", cpus.to_string()))
+        })
+        .transpose()?;
+    resources.set_cpus(cpus)?;
+
+    resources.set_cpuset_cpus(matches.value_of("cpuset-cpus").map(String::from))?;
+    resources.set_cpuset_mems(matches.value_of("cpuset-mems").map(String::from))?;
+
+    if let Some(pids_limit) = matches.value_of("pids-limit") {
+        resources.set_pids_limit(pids_limit_option(pids_limit)?)?;
+    }
+
+    let blkio_weight = matches
+        .value_of("blkio-weight")
+        .map(|weight| {
+            weight
+                .parse()
+                .map_err(|_| ResourcesError::InvalidValue("blkio-weight", weight.to_string()))
+        })
+        .transpose()?;
+    resources.set_blkio_weight(blkio_weight)?;
+
+    let device_rates = |name, parse: fn(&str) -> Result<DeviceRate, ResourcesError>| {
+        matches
+            .values_of(name)
+            .map_or(Ok(vec![]), |rates| rates.map(parse).collect())
+    };
+    resources.set_device_read_bps(device_rates("device-read-bps", DeviceRate::parse_bps)?);
+    resources.set_device_write_bps(device_rates("device-write-bps", DeviceRate::parse_bps)?);
+    resources.set_device_read_iops(device_rates("device-read-iops", DeviceRate::parse_iops)?);
+    resources.set_device_write_iops(device_rates("device-write-iops", DeviceRate::parse_iops)?);
+
+    Ok(())
+}
@@ -1,7 +1,11 @@
 use clap::ArgMatches;
-use failure::Error;
+use failure::{format_err, Error};
 use uuid::Uuid;
 
+use super::options;
+use crate::jocker::monitor;
+use crate::jocker::network::NetworkMode;
+use crate::jocker::userns;
 use crate::jocker::Config;
 
 pub fn run(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
@@ -13,24 +17,69 @@ pub fn run(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
         Uuid::new_v4().to_string()
     };
     let image_name = matches.value_of("IMAGE").unwrap();
+    let detach = matches.is_present("detach");
 
-    println!(
-        "Creating container with ID {} from image {}",
-        container_id, image_name
-    );
-    let container_store = config.container_store();
-    let container =
-        container_store.create_container(container_id.clone(), image_name.to_string())?;
+    if !detach {
+        println!(
+            "Creating container with ID {} from image {}",
+            container_id, image_name
+        );
+    }
+    let image_store = config.image_store();
+    let image = image_store
+        .get_image(image_name)
+        .ok_or_else(|| format_err!("no such image {}", image_name))?;
+    let image_digest = image.manifest_digest()?;
+    let image_config = image_store.config(&image_store.manifest_from_digest(&image_digest)?)?;
+    let command = options::image_command(matches, image_config.execution_config())?;
 
-    println!("Running container with ID {}", container_id);
-    let mut cmd_args = Vec::new();
-    cmd_args.push(matches.value_of("COMMAND").unwrap());
-    if let Some(args) = matches.values_of("ARG") {
-        cmd_args.extend(args);
+    let container_store = config.container_store();
+    let mut container = container_store.create_container(
+        container_id.clone(),
+        image_name.to_string(),
+        image_digest,
+    )?;
+    // Remove the container if any option is invalid rather than leaving it half-configured
+    let configure_result: Result<(), Error> = try {
+        container
+            .config_mut()
+            .apply_image_defaults(image_config.execution_config());
+        let network_mode = match matches.value_of("network") {
+            Some(network_mode) => network_mode.parse()?,
+            // Unprivileged users cannot create the interfaces needed by the bridge mode
+            None if userns::is_rootless() => NetworkMode::Host,
+            None => NetworkMode::Bridge,
+        };
+        container.config_mut().set_network_mode(network_mode);
+        container.config_mut().set_tty(matches.is_present("tty"));
+        container.config_mut().set_init(matches.is_present("init"));
+        container
+            .config_mut()
+            .set_auto_remove(matches.is_present("rm"));
+        container
+            .config_mut()
+            .set_open_stdin(matches.is_present("interactive"));
+        options::apply_container_options(container.config_mut(), matches)?;
+        options::apply_resource_options(container.config_mut().resources_mut(), matches)?;
+        options::apply_log_options(container.config_mut().log_config_mut(), matches)?;
+        container.save_config()?;
+    };
+    if let Err(e) = configure_result {
+        container_store.remove_container(container)?;
+        return Err(e);
     }
-    let cmd = cmd_args.join(" ");
 
-    container.run_command(config, &cmd)?;
+    if detach {
+        monitor::run_detached(config, &mut container, &command)?;
+        println!("{}", container_id);
+    } else {
+        println!("Running container with ID {}", container_id);
+        let result = container.run_command(config, &command);
+        if container.config().auto_remove() {
+            container_store.remove_container(container)?;
+        }
+        result?;
+    }
 
     Ok(())
 }
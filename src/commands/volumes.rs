@@ -0,0 +1,101 @@
+use clap::ArgMatches;
+use failure::Error;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::jocker::Config;
+
+/// Retrieve the names of the containers mounting a given volume
+fn containers_using(config: &Config, volume_name: &str) -> Result<Vec<String>, Error> {
+    let container_store = config.container_store();
+    let mut names = Vec::new();
+
+    for container in container_store.containers()? {
+        let container = container?;
+        let uses_volume = container
+            .config()
+            .mounts()
+            .iter()
+            .any(|mount| mount.uses_volume(volume_name));
+
+        if uses_volume {
+            names.push(container.name().to_string());
+        }
+    }
+
+    Ok(names)
+}
+
+pub fn create(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let name = match matches.value_of("NAME") {
+        Some(name) => name.to_string(),
+        None => Uuid::new_v4().to_string(),
+    };
+    let volume_store = config.volume_store();
+
+    let volume = volume_store.create_volume(&name)?;
+    println!("{}", volume.name().display());
+
+    Ok(())
+}
+
+pub fn list(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let volume_store = config.volume_store();
+
+    if matches.is_present("quiet") {
+        for volume in volume_store.volumes()? {
+            println!("{}", volume?.name().display());
+        }
+    } else {
+        for volume in volume_store.volumes()? {
+            let volume = volume?;
+            println!("{}: {}", volume.name().display(), volume.path().display());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn inspect(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let volume_store = config.volume_store();
+
+    for volume_name in matches.values_of("VOLUME").unwrap() {
+        if let Some(volume) = volume_store.get_volume(volume_name) {
+            let description = json!({
+                "name": volume_name,
+                "path": volume.path(),
+                "containers": containers_using(config, volume_name)?,
+            });
+            println!("{}", serde_json::to_string_pretty(&description)?);
+        } else {
+            println!("unable to inspect {}: no such volume", volume_name);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn remove(config: &Config, matches: &ArgMatches) -> Result<(), Error> {
+    let volume_store = config.volume_store();
+
+    for volume_name in matches.values_of("VOLUME").unwrap() {
+        if let Some(volume) = volume_store.get_volume(volume_name) {
+            let containers = containers_using(config, volume_name)?;
+
+            if containers.is_empty() {
+                volume_store.remove_volume(volume)?;
+                println!("{}: removed", volume_name);
+            } else {
+                println!(
+                    "unable to remove {}: volume is used by {}",
+                    volume_name,
+                    containers.join(", ")
+                );
+            }
+        } else {
+            println!("unable to remove {}: no such volume", volume_name);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,354 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use failure::Fail;
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::stat::Mode;
+use nix::sys::wait::waitpid;
+use nix::unistd::{close, dup2, fork, pipe2, read, ForkResult, Pid};
+
+use super::tty;
+
+/// Key sequence detaching from a container, as accepted by `--detach-keys`
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// Delay after which a client not reading the output of the container is disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum length of the content of a message, which is sent on 16 bits
+const MAX_MESSAGE_LEN: usize = 0xffff;
+
+/// Kind of the messages of a client holding its input
+const INPUT_MESSAGE: u8 = 0;
+
+/// Kind of the messages of a client holding the window size of its terminal
+const WINDOW_SIZE_MESSAGE: u8 = 1;
+
+/// Error type for attach-related errors
+#[derive(Fail, Debug)]
+pub enum AttachError {
+    /// The attach socket of a container could not be created
+    #[fail(display = "cannot listen on the attach socket: {}", _0)]
+    CannotListen(std::io::Error),
+
+    /// The process serving the attach socket could not be created
+    #[fail(display = "cannot fork the attach server: {}", _0)]
+    CannotFork(nix::Error),
+
+    /// The standard streams could not be redirected to the attach socket
+    #[fail(display = "cannot redirect the standard streams: {}", _0)]
+    CannotRedirect(nix::Error),
+
+    /// The attach socket of a container could not be connected to
+    #[fail(display = "cannot attach to the container: {}", _0)]
+    CannotConnect(std::io::Error),
+
+    /// Data could not be exchanged with an attached container
+    #[fail(display = "cannot communicate with the container: {}", _0)]
+    CannotCommunicate(std::io::Error),
+
+    /// A detach key sequence could not be parsed
+    #[fail(display = "invalid detach keys {}", _0)]
+    InvalidDetachKeys(String),
+}
+
+/// Parse a detach key sequence given as a comma-separated list of characters or `ctrl-<key>`
+/// combinations, such as `ctrl-p,ctrl-q`
+pub fn parse_detach_keys(s: &str) -> Result<Vec<u8>, AttachError> {
+    let invalid = || AttachError::InvalidDetachKeys(s.to_string());
+
+    s.split(',')
+        .map(|key| {
+            let key = key.trim();
+            let (control, key) = if key.len() > 5 && key[..5].eq_ignore_ascii_case("ctrl-") {
+                (true, &key[5..])
+            } else {
+                (false, key)
+            };
+
+            match (control, key.as_bytes()) {
+                (false, &[byte]) if byte.is_ascii() => Ok(byte),
+                (true, &[byte]) => match byte.to_ascii_uppercase() {
+                    byte @ b'@'..=b'_' => Ok(byte - b'@'),
+                    _ => Err(invalid()),
+                },
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+/// Send a message to the server, made of its kind, the length of its content and its content
+fn write_message(stream: &mut UnixStream, kind: u8, content: &[u8]) -> std::io::Result<()> {
+    for chunk in content.chunks(MAX_MESSAGE_LEN) {
+        let length = (chunk.len() as u16).to_be_bytes();
+        stream.write_all(&[kind, length[0], length[1]])?;
+        stream.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+/// Receive a message sent by a client with [`write_message`], returning its kind
+fn read_message(stream: &mut UnixStream, content: &mut Vec<u8>) -> std::io::Result<u8> {
+    let mut header = [0; 3];
+    stream.read_exact(&mut header)?;
+    content.resize(u16::from_be_bytes([header[1], header[2]]) as usize, 0);
+    stream.read_exact(content)?;
+    Ok(header[0])
+}
+
+/// Serve the output read from a pipe to the clients accepted on a socket, passing their input
+/// to another pipe if `open_stdin` is set and the window sizes of their terminals to a last
+/// pipe, until the output ends
+fn serve(
+    listener: UnixListener,
+    output_read: RawFd,
+    input_write: RawFd,
+    window_sizes_write: RawFd,
+    open_stdin: bool,
+) {
+    // The list of clients is dropped once the output ends, so that no client is left waiting
+    let clients = Arc::new(Mutex::new(Some(Vec::<UnixStream>::new())));
+    let input = Arc::new(unsafe { File::from_raw_fd(input_write) });
+    let window_sizes = Arc::new(unsafe { File::from_raw_fd(window_sizes_write) });
+
+    let accepted_clients = clients.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let _ = stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT));
+
+            match (
+                accepted_clients.lock().unwrap().as_mut(),
+                stream.try_clone(),
+            ) {
+                (Some(clients), Ok(client)) => clients.push(client),
+                _ => continue,
+            }
+            let input = input.clone();
+            let window_sizes = window_sizes.clone();
+            thread::spawn(move || {
+                let mut stream = stream;
+                let mut content = Vec::new();
+                while let Ok(kind) = read_message(&mut stream, &mut content) {
+                    match kind {
+                        INPUT_MESSAGE if open_stdin => {
+                            if (&*input).write_all(&content).is_err() {
+                                break;
+                            }
+                        }
+                        // Sizes are dropped rather than blocking the client if they are not read
+                        WINDOW_SIZE_MESSAGE if content.len() == tty::WINDOW_SIZE_LEN => {
+                            let _ = (&*window_sizes).write(&content);
+                        }
+                        _ => {}
+                    }
+                }
+            });
+        }
+    });
+
+    let mut output = unsafe { File::from_raw_fd(output_read) };
+    let mut buffer = [0; 4096];
+    loop {
+        let count = match output.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => count,
+        };
+        if let Some(clients) = clients.lock().unwrap().as_mut() {
+            clients.retain(|client| (&*client).write_all(&buffer[..count]).is_ok());
+        }
+    }
+
+    for client in clients.lock().unwrap().take().into_iter().flatten() {
+        let _ = client.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// Structure serving the standard streams of the current process on a Unix socket
+///
+/// The output written to the standard output and error is sent to every attached client, and
+/// the input of the clients is passed to the standard input if it is kept open. The window
+/// sizes of the terminals of the clients can be read from another pipe.
+pub struct AttachServer {
+    socket_path: PathBuf,
+    server: Pid,
+    window_sizes: RawFd,
+}
+
+impl AttachServer {
+    /// Start serving the standard streams on a socket at a given path
+    pub fn start(socket_path: &Path, open_stdin: bool) -> Result<Self, AttachError> {
+        let _ = fs::remove_file(socket_path);
+        let listener = UnixListener::bind(socket_path).map_err(AttachError::CannotListen)?;
+
+        let (output_read, output_write) =
+            pipe2(OFlag::O_CLOEXEC).map_err(AttachError::CannotRedirect)?;
+        let (input_read, input_write) =
+            pipe2(OFlag::O_CLOEXEC).map_err(AttachError::CannotRedirect)?;
+        let (window_sizes_read, window_sizes_write) =
+            pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK).map_err(AttachError::CannotRedirect)?;
+
+        // The server runs in a process of its own, since the caller must stay single-threaded
+        // for the processes it clones to be able to change their credentials
+        let server = match fork().map_err(AttachError::CannotFork)? {
+            ForkResult::Parent { child } => child,
+            ForkResult::Child => {
+                let _ = close(output_write)
+                    .and_then(|_| close(input_read))
+                    .and_then(|_| close(window_sizes_read));
+                serve(
+                    listener,
+                    output_read,
+                    input_write,
+                    window_sizes_write,
+                    open_stdin,
+                );
+                std::process::exit(0);
+            }
+        };
+        drop(listener);
+
+        let redirect_result: Result<(), nix::Error> = try {
+            close(output_read)?;
+            close(input_write)?;
+            close(window_sizes_write)?;
+            dup2(output_write, 1)?;
+            dup2(output_write, 2)?;
+            close(output_write)?;
+            if open_stdin {
+                dup2(input_read, 0)?;
+            }
+            close(input_read)?;
+        };
+        redirect_result.map_err(AttachError::CannotRedirect)?;
+
+        Ok(Self {
+            socket_path: socket_path.to_path_buf(),
+            server,
+            window_sizes: window_sizes_read,
+        })
+    }
+
+    /// Retrieve the pipe from which the window sizes of the terminals of the clients are read,
+    /// as encoded by [`tty::encode_window_size`]
+    pub fn window_sizes(&self) -> RawFd {
+        self.window_sizes
+    }
+
+    /// Stop serving the standard streams, once the output written so far has been sent
+    pub fn stop(self) {
+        let _ = fs::remove_file(&self.socket_path);
+
+        // The output ends when its last writer, the standard streams, are closed
+        if let Ok(null_fd) = open(Path::new("/dev/null"), OFlag::O_RDWR, Mode::empty()) {
+            let _ = dup2(null_fd, 1).and_then(|_| dup2(null_fd, 2));
+            let _ = close(null_fd);
+            let _ = waitpid(self.server, None);
+        }
+        let _ = close(self.window_sizes);
+    }
+}
+
+/// Connect the standard streams to a container served by an [`AttachServer`], until the
+/// container exits or the detach key sequence is typed
+///
+/// The window size of the terminal is sent to the container whenever it changes.
+///
+/// Returns whether the client detached from the container.
+pub fn attach(socket_path: &Path, detach_keys: &[u8]) -> Result<bool, AttachError> {
+    let mut stream = UnixStream::connect(socket_path).map_err(AttachError::CannotConnect)?;
+    tty::watch_window_resizes();
+    let result = forward_streams(&mut stream, detach_keys);
+    tty::unwatch_window_resizes();
+    result
+}
+
+/// Exchange the standard streams with an attached container, see [`attach`]
+fn forward_streams(stream: &mut UnixStream, detach_keys: &[u8]) -> Result<bool, AttachError> {
+    let mut stdout = std::io::stdout();
+    let mut input_open = true;
+    let mut matched = 0;
+    let mut buffer = [0; 4096];
+
+    loop {
+        if tty::window_resized() {
+            if let Some(size) = tty::window_size() {
+                write_message(stream, WINDOW_SIZE_MESSAGE, &tty::encode_window_size(&size))
+                    .map_err(AttachError::CannotCommunicate)?;
+            }
+        }
+
+        let mut poll_fds = vec![PollFd::new(stream.as_raw_fd(), PollFlags::POLLIN)];
+        if input_open {
+            poll_fds.push(PollFd::new(0 as RawFd, PollFlags::POLLIN));
+        }
+        match poll(&mut poll_fds, -1) {
+            Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(_) => {
+                return Err(AttachError::CannotCommunicate(
+                    std::io::Error::last_os_error(),
+                ))
+            }
+        }
+        let ready = |poll_fd: &PollFd| {
+            poll_fd
+                .revents()
+                .map_or(false, |revents| !revents.is_empty())
+        };
+
+        if ready(&poll_fds[0]) {
+            // The server closes the connection once the container has exited
+            let count = stream
+                .read(&mut buffer)
+                .map_err(AttachError::CannotCommunicate)?;
+            if count == 0 {
+                return Ok(false);
+            }
+            stdout
+                .write_all(&buffer[..count])
+                .and_then(|_| stdout.flush())
+                .map_err(AttachError::CannotCommunicate)?;
+        }
+
+        if poll_fds.len() > 1 && ready(&poll_fds[1]) {
+            let count = read(0, &mut buffer).unwrap_or(0);
+            if count == 0 {
+                input_open = false;
+                continue;
+            }
+
+            // Keys matching the beginning of the detach sequence are held back until it is
+            // either completed or broken
+            let mut input = Vec::with_capacity(count);
+            for byte in &buffer[..count] {
+                if matched > 0 && detach_keys.get(matched) != Some(byte) {
+                    input.extend_from_slice(&detach_keys[..matched]);
+                    matched = 0;
+                }
+                if detach_keys.get(matched) == Some(byte) {
+                    matched += 1;
+                    if matched == detach_keys.len() {
+                        write_message(stream, INPUT_MESSAGE, &input)
+                            .map_err(AttachError::CannotCommunicate)?;
+                        return Ok(true);
+                    }
+                } else {
+                    input.push(*byte);
+                }
+            }
+            write_message(stream, INPUT_MESSAGE, &input).map_err(AttachError::CannotCommunicate)?;
+        }
+    }
+}
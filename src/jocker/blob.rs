@@ -0,0 +1,230 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use failure::Fail;
+use sha2::{Digest, Sha256};
+
+/// Prefix of the digests computed with SHA-256, the only algorithm supported
+pub const SHA256_PREFIX: &str = "sha256:";
+
+/// Error type for blob-related errors
+#[derive(Fail, Debug)]
+pub enum BlobError {
+    /// A digest is not a valid SHA-256 digest
+    #[fail(display = "invalid digest {}", _0)]
+    InvalidDigest(String),
+
+    /// A blob is missing from the store
+    #[fail(display = "blob {} not found", _0)]
+    BlobNotFound(String),
+
+    /// The content of a blob does not match its digest
+    #[fail(display = "digest mismatch, expected {}, got {}", _0, _1)]
+    DigestMismatch(String, String),
+
+    /// A blob could not be written to the store
+    #[fail(display = "unable to store blob: {}", _0)]
+    CannotStoreBlob(std::io::Error),
+
+    /// A blob could not be read from the store
+    #[fail(display = "unable to read blob: {}", _0)]
+    CannotReadBlob(std::io::Error),
+
+    /// A blob could not be removed from the store
+    #[fail(display = "unable to remove blob: {}", _0)]
+    CannotRemoveBlob(std::io::Error),
+}
+
+/// Extract the hexadecimal part of a SHA-256 digest, such as `sha256:e3b0c442...`
+pub fn digest_hex(digest: &str) -> Result<&str, BlobError> {
+    let invalid = || BlobError::InvalidDigest(digest.to_string());
+
+    if !digest.starts_with(SHA256_PREFIX) {
+        return Err(invalid());
+    }
+    let hex = &digest[SHA256_PREFIX.len()..];
+    if hex.len() != 64
+        || !hex
+            .bytes()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+    {
+        return Err(invalid());
+    }
+
+    Ok(hex)
+}
+
+/// Reader computing the digest of the data read through it
+pub struct DigestReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> DigestReader<R> {
+    /// Create a [`DigestReader`] reading from another reader
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Read the remaining data, and return the digest and the size of all the data read
+    pub fn finish(mut self) -> Result<(String, u64), std::io::Error> {
+        std::io::copy(&mut self, &mut std::io::sink())?;
+
+        Ok((
+            format!("{}{:x}", SHA256_PREFIX, self.hasher.result()),
+            self.size,
+        ))
+    }
+
+    /// Read the remaining data, and check that the digest of all the data read is the expected
+    /// one
+    pub fn verify(self, expected: &str) -> Result<(), BlobError> {
+        let (digest, _) = self.finish().map_err(BlobError::CannotReadBlob)?;
+
+        if digest == expected {
+            Ok(())
+        } else {
+            Err(BlobError::DigestMismatch(expected.to_string(), digest))
+        }
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        let count = self.inner.read(buf)?;
+
+        self.hasher.input(&buf[..count]);
+        self.size += count as u64;
+        Ok(count)
+    }
+}
+
+/// Structure representing a handle over a directory storing blobs by the digest of their
+/// content, so that identical blobs are only stored once
+#[derive(Debug)]
+pub struct BlobStore<'a> {
+    blobs_dir: &'a Path,
+}
+
+impl<'a> BlobStore<'a> {
+    /// Create a [`BlobStore`] from a path
+    pub fn from_directory(blobs_dir: &'a Path) -> Self {
+        Self { blobs_dir }
+    }
+
+    /// Retrieve the path to the root directory for this store
+    pub fn path(&self) -> &Path {
+        &self.blobs_dir
+    }
+
+    /// Retrieve the path to the blob with a given digest, which may not exist
+    pub fn blob_path(&self, digest: &str) -> Result<PathBuf, BlobError> {
+        Ok(self.blobs_dir.join("sha256").join(digest_hex(digest)?))
+    }
+
+    /// Obtain an iterator over the digests of the blobs available in this store
+    pub fn digests(&self) -> Result<impl Iterator<Item = String>, std::io::Error> {
+        let entries = match fs::read_dir(self.blobs_dir.join("sha256")) {
+            Ok(entries) => Some(entries),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(entries
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .map(|hex| format!("{}{}", SHA256_PREFIX, hex))
+            .filter(|digest| digest_hex(digest).is_ok()))
+    }
+
+    /// Store a blob read from a reader, checking its digest if an expected one is given, and
+    /// return its digest and its size
+    pub fn put<R: Read>(
+        &self,
+        reader: R,
+        expected: Option<&str>,
+    ) -> Result<(String, u64), BlobError> {
+        let blobs_dir = self.blobs_dir.join("sha256");
+        fs::create_dir_all(&blobs_dir).map_err(BlobError::CannotStoreBlob)?;
+
+        // The blob is written aside, since its path is only known once it is entirely read
+        let tmp_path = self
+            .blobs_dir
+            .join(format!(".tmp-{}", uuid::Uuid::new_v4()));
+        let write_result: Result<(String, u64), std::io::Error> = try {
+            let mut reader = DigestReader::new(reader);
+            let mut file = fs::File::create(&tmp_path)?;
+            std::io::copy(&mut reader, &mut file)?;
+            file.flush()?;
+            file.sync_all()?;
+            reader.finish()?
+        };
+        let (digest, size) = match write_result {
+            Ok(result) => result,
+            Err(e) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(BlobError::CannotStoreBlob(e));
+            }
+        };
+
+        match expected {
+            Some(expected) if expected != digest => {
+                let _ = fs::remove_file(&tmp_path);
+                Err(BlobError::DigestMismatch(expected.to_string(), digest))
+            }
+            _ => {
+                fs::rename(&tmp_path, self.blob_path(&digest)?)
+                    .map_err(BlobError::CannotStoreBlob)?;
+                Ok((digest, size))
+            }
+        }
+    }
+
+    /// Store a blob from a file, checking its digest if an expected one is given
+    pub fn put_file(
+        &self,
+        path: &Path,
+        expected: Option<&str>,
+    ) -> Result<(String, u64), BlobError> {
+        let file = fs::File::open(path).map_err(BlobError::CannotStoreBlob)?;
+
+        self.put(file, expected)
+    }
+
+    /// Open a blob, whose content can be verified with [`DigestReader::verify`] once read
+    pub fn open(&self, digest: &str) -> Result<DigestReader<fs::File>, BlobError> {
+        let path = self.blob_path(digest)?;
+
+        match fs::File::open(&path) {
+            Ok(file) => Ok(DigestReader::new(file)),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(BlobError::BlobNotFound(digest.to_string()))
+            }
+            Err(e) => Err(BlobError::CannotReadBlob(e)),
+        }
+    }
+
+    /// Read the whole content of a blob, after checking its digest
+    pub fn read(&self, digest: &str) -> Result<Vec<u8>, BlobError> {
+        let mut reader = self.open(digest)?;
+        let mut content = Vec::new();
+
+        reader
+            .read_to_end(&mut content)
+            .map_err(BlobError::CannotReadBlob)?;
+        reader.verify(digest)?;
+        Ok(content)
+    }
+
+    /// Remove the blob with a given digest from this store
+    pub fn remove_blob(&self, digest: &str) -> Result<(), BlobError> {
+        fs::remove_file(self.blob_path(digest)?).map_err(BlobError::CannotRemoveBlob)
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::path::Path;
+
+use failure::Fail;
+use nix::unistd::Pid;
+
+use super::resources::Resources;
+
+mod v1;
+mod v2;
+
+pub use self::v1::CgroupV1;
+pub use self::v2::CgroupV2;
+
+/// Path where the control group hierarchies are mounted
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Name of the cgroup grouping all containers in each hierarchy
+const JOCKER_CGROUP: &str = "jocker";
+
+/// Error type for cgroup-related errors
+#[derive(Fail, Debug)]
+pub enum CgroupError {
+    /// A cgroup could not be created
+    #[fail(display = "cannot create cgroup {}: {}", _0, _1)]
+    CannotCreateCgroup(String, std::io::Error),
+
+    /// A cgroup file could not be read
+    #[fail(display = "cannot read {}: {}", _0, _1)]
+    CannotReadFile(String, std::io::Error),
+
+    /// A cgroup file could not be written
+    #[fail(display = "cannot write {}: {}", _0, _1)]
+    CannotWriteFile(String, std::io::Error),
+}
+
+/// Read a cgroup file
+fn read_file(path: &Path) -> Result<String, CgroupError> {
+    fs::read_to_string(path).map_err(|e| CgroupError::CannotReadFile(path.display().to_string(), e))
+}
+
+/// Write a value to a cgroup file
+fn write_file(path: &Path, value: &str) -> Result<(), CgroupError> {
+    fs::write(path, value).map_err(|e| CgroupError::CannotWriteFile(path.display().to_string(), e))
+}
+
+/// Create a cgroup directory, if it does not exist already
+fn create_dir(path: &Path) -> Result<(), CgroupError> {
+    if path.exists() {
+        Ok(())
+    } else {
+        fs::create_dir_all(path)
+            .map_err(|e| CgroupError::CannotCreateCgroup(path.display().to_string(), e))
+    }
+}
+
+/// Read a counter from a file made of `key value` lines, such as `memory.events`
+fn read_counter(path: &Path, key: &str) -> Option<u64> {
+    let content = fs::read_to_string(path).ok()?;
+
+    content.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        if fields.next() == Some(key) {
+            fields.next()?.parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Trait for the control groups of a container, independently of the hierarchy in use
+pub trait Cgroup {
+    /// Create the control groups of the container and apply its resource limits
+    fn setup(&self, resources: &Resources) -> Result<(), CgroupError>;
+
+    /// Move a process to the control groups of the container
+    fn join(&self, pid: Pid) -> Result<(), CgroupError>;
+
+    /// Check whether processes of the container were killed for exceeding the memory limit
+    fn oom_killed(&self) -> bool;
+
+    /// Check whether processes of the container failed to fork for exceeding the pids limit
+    fn pids_limit_reached(&self) -> bool;
+
+    /// Remove the control groups of the container, which must not have processes anymore
+    fn remove(&self);
+}
+
+/// Check whether the host uses the unified (v2) control group hierarchy
+pub fn is_unified_hierarchy() -> bool {
+    Path::new(CGROUP_ROOT).join("cgroup.controllers").exists()
+}
+
+/// Obtain a handle over the control groups of a container, for the hierarchy used by the host
+pub fn for_container(name: &str) -> Box<dyn Cgroup> {
+    if is_unified_hierarchy() {
+        Box::new(CgroupV2::new(name))
+    } else {
+        Box::new(CgroupV1::new(name))
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+
+use nix::unistd::Pid;
+
+use super::{create_dir, read_counter, read_file, write_file};
+use super::{Cgroup, CgroupError, CGROUP_ROOT, JOCKER_CGROUP};
+use crate::jocker::resources::{Resources, CPU_PERIOD};
+
+/// Controllers the containers are attached to, each having its own hierarchy
+const CONTROLLERS: &[&str] = &["blkio", "cpu", "cpuset", "memory", "pids"];
+
+/// Structure representing the control groups of a container in the legacy (v1) hierarchies
+pub struct CgroupV1 {
+    name: String,
+}
+
+impl CgroupV1 {
+    /// Create a handle over the control groups of the container with the given name
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+
+    /// Retrieve the path to the cgroup of the container in a given hierarchy
+    fn path(&self, controller: &str) -> PathBuf {
+        Path::new(CGROUP_ROOT)
+            .join(controller)
+            .join(JOCKER_CGROUP)
+            .join(&self.name)
+    }
+
+    fn setup_memory(&self, resources: &Resources) -> Result<(), CgroupError> {
+        let path = self.path("memory");
+        create_dir(&path)?;
+
+        // The memory limit has to be set first, as the kernel requires it to be lower
+        // than the memory and swap limit
+        if let Some(memory) = resources.memory() {
+            write_file(&path.join("memory.limit_in_bytes"), &memory.to_string())?;
+        }
+        if let Some(memory_swap) = resources.memory_swap() {
+            write_file(
+                &path.join("memory.memsw.limit_in_bytes"),
+                &memory_swap.to_string(),
+            )?;
+        }
+        if let Some(swappiness) = resources.memory_swappiness() {
+            write_file(&path.join("memory.swappiness"), &swappiness.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn setup_cpu(&self, resources: &Resources) -> Result<(), CgroupError> {
+        let path = self.path("cpu");
+        create_dir(&path)?;
+
+        if let Some(cpu_shares) = resources.cpu_shares() {
+            write_file(&path.join("cpu.shares"), &cpu_shares.to_string())?;
+        }
+        if let Some(cpu_quota) = resources.cpu_quota() {
+            write_file(&path.join("cpu.cfs_period_us"), &CPU_PERIOD.to_string())?;
+            write_file(&path.join("cpu.cfs_quota_us"), &cpu_quota.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn setup_cpuset(&self, resources: &Resources) -> Result<(), CgroupError> {
+        let path = self.path("cpuset");
+        create_dir(&path)?;
+
+        // Tasks cannot join a cpuset before it is given CPUs and memory nodes, which
+        // new cpusets do not inherit: fill the empty ones from their parent
+        for file_name in &["cpuset.cpus", "cpuset.mems"] {
+            for cpuset_path in &[path.parent().unwrap(), &path] {
+                let file_path = cpuset_path.join(file_name);
+                if read_file(&file_path)?.trim().is_empty() {
+                    let parent_value = read_file(&cpuset_path.parent().unwrap().join(file_name))?;
+                    write_file(&file_path, parent_value.trim())?;
+                }
+            }
+        }
+
+        if let Some(cpus) = resources.cpuset_cpus() {
+            write_file(&path.join("cpuset.cpus"), cpus)?;
+        }
+        if let Some(mems) = resources.cpuset_mems() {
+            write_file(&path.join("cpuset.mems"), mems)?;
+        }
+
+        Ok(())
+    }
+
+    fn setup_blkio(&self, resources: &Resources) -> Result<(), CgroupError> {
+        let path = self.path("blkio");
+        create_dir(&path)?;
+
+        if let Some(weight) = resources.blkio_weight() {
+            // Kernels using the BFQ scheduler only expose its own weight file
+            let weight_path = if path.join("blkio.weight").exists() {
+                path.join("blkio.weight")
+            } else {
+                path.join("blkio.bfq.weight")
+            };
+            write_file(&weight_path, &weight.to_string())?;
+        }
+
+        let throttles = [
+            (
+                "blkio.throttle.read_bps_device",
+                resources.device_read_bps(),
+            ),
+            (
+                "blkio.throttle.write_bps_device",
+                resources.device_write_bps(),
+            ),
+            (
+                "blkio.throttle.read_iops_device",
+                resources.device_read_iops(),
+            ),
+            (
+                "blkio.throttle.write_iops_device",
+                resources.device_write_iops(),
+            ),
+        ];
+        for (file_name, rates) in &throttles {
+            for rate in rates.iter() {
+                write_file(
+                    &path.join(file_name),
+                    &format!("{} {}", rate.device(), rate.rate()),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn setup_pids(&self, resources: &Resources) -> Result<(), CgroupError> {
+        let path = self.path("pids");
+        create_dir(&path)?;
+
+        if let Some(pids_limit) = resources.pids_limit() {
+            write_file(&path.join("pids.max"), &pids_limit.to_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Cgroup for CgroupV1 {
+    fn setup(&self, resources: &Resources) -> Result<(), CgroupError> {
+        self.setup_memory(resources)?;
+        self.setup_cpu(resources)?;
+        self.setup_cpuset(resources)?;
+        self.setup_pids(resources)?;
+        self.setup_blkio(resources)
+    }
+
+    fn join(&self, pid: Pid) -> Result<(), CgroupError> {
+        for controller in CONTROLLERS {
+            write_file(
+                &self.path(controller).join("cgroup.procs"),
+                &pid.to_string(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn oom_killed(&self) -> bool {
+        read_counter(&self.path("memory").join("memory.oom_control"), "oom_kill")
+            .map_or(false, |count| count > 0)
+    }
+
+    fn pids_limit_reached(&self) -> bool {
+        read_counter(&self.path("pids").join("pids.events"), "max").map_or(false, |count| count > 0)
+    }
+
+    fn remove(&self) {
+        for controller in CONTROLLERS {
+            let _ = std::fs::remove_dir(self.path(controller));
+        }
+    }
+}
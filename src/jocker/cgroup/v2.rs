@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+use nix::unistd::Pid;
+
+use super::{create_dir, read_counter, read_file, write_file};
+use super::{Cgroup, CgroupError, CGROUP_ROOT, JOCKER_CGROUP};
+use crate::jocker::resources::{Resources, CPU_PERIOD};
+
+/// Controllers the containers use, when available
+const CONTROLLERS: &[&str] = &["cpu", "cpuset", "io", "memory", "pids"];
+
+/// Structure representing the control group of a container in the unified (v2) hierarchy
+pub struct CgroupV2 {
+    name: String,
+}
+
+impl CgroupV2 {
+    /// Create a handle over the control group of the container with the given name
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+
+    /// Retrieve the path to the cgroup grouping all containers
+    fn jocker_path() -> PathBuf {
+        Path::new(CGROUP_ROOT).join(JOCKER_CGROUP)
+    }
+
+    /// Retrieve the path to the cgroup of the container
+    fn path(&self) -> PathBuf {
+        Self::jocker_path().join(&self.name)
+    }
+
+    /// Delegate the controllers used by containers from a cgroup to its children
+    fn enable_controllers(path: &Path) -> Result<(), CgroupError> {
+        let available = read_file(&path.join("cgroup.controllers"))?;
+        let enabled = available
+            .split_whitespace()
+            .filter(|controller| CONTROLLERS.contains(controller))
+            .map(|controller| format!("+{}", controller))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        write_file(&path.join("cgroup.subtree_control"), &enabled)
+    }
+}
+
+impl Cgroup for CgroupV2 {
+    fn setup(&self, resources: &Resources) -> Result<(), CgroupError> {
+        let path = self.path();
+
+        Self::enable_controllers(Path::new(CGROUP_ROOT))?;
+        create_dir(&Self::jocker_path())?;
+        Self::enable_controllers(&Self::jocker_path())?;
+        create_dir(&path)?;
+
+        if let Some(memory) = resources.memory() {
+            write_file(&path.join("memory.max"), &memory.to_string())?;
+        }
+        // Unlike in v1, the swap limit does not include the memory
+        if let (Some(memory), Some(memory_swap)) = (resources.memory(), resources.memory_swap()) {
+            write_file(
+                &path.join("memory.swap.max"),
+                &(memory_swap - memory).to_string(),
+            )?;
+        }
+        // The swappiness cannot be set per cgroup in v2, only the global one applies
+
+        if let Some(cpu_shares) = resources.cpu_shares() {
+            // Map the shares from [2, 262144] to weights in [1, 10000]
+            let weight = 1 + ((cpu_shares.min(262_144) - 2) * 9999) / 262_142;
+            write_file(&path.join("cpu.weight"), &weight.to_string())?;
+        }
+        if let Some(cpu_quota) = resources.cpu_quota() {
+            write_file(
+                &path.join("cpu.max"),
+                &format!("{} {}", cpu_quota, CPU_PERIOD),
+            )?;
+        }
+
+        if let Some(cpus) = resources.cpuset_cpus() {
+            write_file(&path.join("cpuset.cpus"), cpus)?;
+        }
+        if let Some(mems) = resources.cpuset_mems() {
+            write_file(&path.join("cpuset.mems"), mems)?;
+        }
+
+        if let Some(pids_limit) = resources.pids_limit() {
+            write_file(&path.join("pids.max"), &pids_limit.to_string())?;
+        }
+
+        if let Some(blkio_weight) = resources.blkio_weight() {
+            // Map the weights from [10, 1000] to the [1, 10000] range of io.weight, and use the
+            // file of the BFQ scheduler, which takes them as they are, when the kernel only
+            // exposes this one
+            let (weight_path, weight) = if path.join("io.weight").exists() {
+                let weight = 1 + ((blkio_weight - 10) * 9999) / 990;
+                (path.join("io.weight"), weight)
+            } else {
+                (path.join("io.bfq.weight"), blkio_weight)
+            };
+            write_file(&weight_path, &format!("default {}", weight))?;
+        }
+
+        let throttles = [
+            ("rbps", resources.device_read_bps()),
+            ("wbps", resources.device_write_bps()),
+            ("riops", resources.device_read_iops()),
+            ("wiops", resources.device_write_iops()),
+        ];
+        for (key, rates) in &throttles {
+            for rate in rates.iter() {
+                write_file(
+                    &path.join("io.max"),
+                    &format!("{} {}={}", rate.device(), key, rate.rate()),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn join(&self, pid: Pid) -> Result<(), CgroupError> {
+        write_file(&self.path().join("cgroup.procs"), &pid.to_string())
+    }
+
+    fn oom_killed(&self) -> bool {
+        read_counter(&self.path().join("memory.events"), "oom_kill")
+            .map_or(false, |count| count > 0)
+    }
+
+    fn pids_limit_reached(&self) -> bool {
+        read_counter(&self.path().join("pids.events"), "max").map_or(false, |count| count > 0)
+    }
+
+    fn remove(&self) {
+        let _ = std::fs::remove_dir(self.path());
+    }
+}
@@ -1,20 +1,48 @@
 use std::ffi::CString;
 use std::fs;
-use std::io::Write;
+use std::net::Ipv4Addr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, FileTypeExt, MetadataExt};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use failure::{format_err, Error, Fail, ResultExt};
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use nix::mount::{mount, umount, umount2, MntFlags, MsFlags};
-use nix::sched::{clone, CloneFlags};
-use nix::sys::signal::SIGCHLD;
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::libc;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::sched::{clone, setns, CloneFlags};
+use nix::sys::signal::{
+    kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal, SIGCHLD, SIGKILL, SIGTERM,
+};
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
 use nix::sys::stat::{fchmodat, makedev, mknod, FchmodatFlags, Mode, SFlag};
 use nix::sys::wait::{waitpid, WaitStatus};
-use nix::unistd::{chdir, execv, getpid, pivot_root, sethostname};
+use nix::unistd::{
+    chdir, close, dup2, execve, fexecve, fork, getpid, pipe, pipe2, pivot_root, read, setgid,
+    setgroups, sethostname, setuid, write, ForkResult, Gid, Pid,
+};
 use serde_derive::{Deserialize, Serialize};
 
-use super::image::{ExtractedImage, ImageError};
+use super::cgroup::{self, Cgroup, CgroupError};
+use super::image::{
+    set_environment_variable, ExecutionConfig, ExtractedImage, ImageConfig, ImageError, Manifest,
+};
+use super::layer;
+use super::logs::{self, LineLogger, LogConfig, LogError, LogStream, LogWriter};
+use super::network::{self, NetworkError, NetworkMode};
+use super::resources::Resources;
+use super::state::{self, ContainerState, ContainerStatus};
+use super::tty::{self, RawMode};
+use super::user::User;
+use super::userns::{self, UserNamespaceError};
+use super::volume::BindMount;
 use super::Config;
 use crate::jocker::image::Image;
 
@@ -72,18 +100,158 @@ pub enum ContainerError {
     /// The container exited abnormally
     #[fail(display = "the container exited abnormally")]
     ContainerExitedAbnormally,
+
+    /// The container was killed because it exceeded its memory limit
+    #[fail(display = "the container ran out of memory")]
+    OutOfMemory,
+
+    /// A command of the container failed after processes could not be created past the pids limit
+    #[fail(display = "the container reached its process limit")]
+    PidsLimitReached,
+
+    /// The network of the container could not be configured
+    #[fail(display = "cannot setup the container's network: {}", _0)]
+    NetworkSetupError(NetworkError),
+
+    /// The container was expected to be running
+    #[fail(display = "the container is not running")]
+    NotRunning,
+
+    /// The control groups of the container could not be configured
+    #[fail(display = "cannot setup the container's control groups: {}", _0)]
+    CgroupSetupError(CgroupError),
+
+    /// The output of the container could not be logged
+    #[fail(display = "cannot log the container's output: {}", _0)]
+    LoggingError(LogError),
+
+    /// The user namespace of the container could not be configured
+    #[fail(display = "cannot setup the container's user namespace: {}", _0)]
+    UserNamespaceSetupError(UserNamespaceError),
+}
+
+/// Build the arguments to run a command line through the shell of a container
+pub fn shell_command(command_line: &str) -> Vec<String> {
+    vec![
+        "/bin/sh".to_string(),
+        "-c".to_string(),
+        command_line.to_string(),
+    ]
+}
+
+/// Default search path for programs executed in containers
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Delay between two checks of the state of a container waiting for it to exit
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Signals received by jocker that are forwarded to the container running in the foreground
+const FORWARDED_SIGNALS: &[Signal] = &[Signal::SIGINT, Signal::SIGTERM];
+
+/// Host identifier of the container running in the foreground, or 0 if there is none
+static FOREGROUND_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pid = FOREGROUND_PID.load(Ordering::SeqCst);
+
+    if pid > 0 {
+        unsafe { libc::kill(pid, signal) };
+    }
+}
+
+/// Forward the signals asking jocker to terminate to a container process, so that stopping
+/// jocker stops the container instead of leaving it behind
+fn forward_signals_to(pid: Pid) -> Result<(), nix::Error> {
+    FOREGROUND_PID.store(pid.as_raw(), Ordering::SeqCst);
+
+    let action = SigAction::new(
+        SigHandler::Handler(forward_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in FORWARDED_SIGNALS {
+        unsafe { sigaction(*signal, &action) }?;
+    }
+    Ok(())
+}
+
+/// Restore the default handling of the signals forwarded by [`forward_signals_to`]
+fn stop_forwarding_signals() {
+    let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+
+    for signal in FORWARDED_SIGNALS {
+        let _ = unsafe { sigaction(*signal, &action) };
+    }
+    FOREGROUND_PID.store(0, Ordering::SeqCst);
+}
+
+/// Report an error which happened while setting up a container process, and exit with the
+/// status telling the parent about it
+fn exit_with_setup_error(error: &Error) -> ! {
+    let fail = error.as_fail();
+    eprint!("error: {}", fail);
+    for cause in fail.iter_causes() {
+        eprint!(": {}", cause);
+    }
+    eprintln!();
+
+    std::process::exit(242);
+}
+
+/// Wait for a child process to exit, even if signals interrupt the wait
+fn wait_for_child(pid: Pid) -> Result<WaitStatus, ContainerError> {
+    loop {
+        match waitpid(pid, None) {
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            result => return result.map_err(ContainerError::ContainerExecutionError),
+        }
+    }
 }
 
 /// Structure describing the configuration of a container
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct ContainerConfig {
     name: String,
     image_name: String,
+    #[serde(default)]
+    image_digest: Option<String>,
+    #[serde(default)]
+    network_mode: NetworkMode,
+    #[serde(default)]
+    ip_address: Option<Ipv4Addr>,
+    #[serde(default)]
+    env: Vec<String>,
+    #[serde(default)]
+    working_dir: Option<PathBuf>,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    mounts: Vec<BindMount>,
+    #[serde(default)]
+    resources: Resources,
+    #[serde(default)]
+    log_config: LogConfig,
+    #[serde(default)]
+    tty: bool,
+    #[serde(default)]
+    open_stdin: bool,
+    #[serde(default)]
+    init: bool,
+    #[serde(default)]
+    auto_remove: bool,
+    #[serde(default)]
+    state: ContainerState,
 }
 
 impl ContainerConfig {
-    fn from(name: String, image_name: String) -> Self {
-        Self { name, image_name }
+    fn from(name: String, image_name: String, image_digest: String) -> Self {
+        Self {
+            name,
+            image_name,
+            image_digest: Some(image_digest),
+            state: ContainerState::new(),
+            ..Default::default()
+        }
     }
 
     /// Load a configuration from a file
@@ -94,12 +262,19 @@ impl ContainerConfig {
     }
 
     /// Save the configuration to a file
+    ///
+    /// The configuration is written to a temporary file which then replaces the previous one, so
+    /// that readers never see a partially written configuration.
     pub fn save(&self, path: &Path) -> Result<(), ContainerError> {
+        let tmp_path = path.with_extension("json.tmp");
         let file =
-            fs::File::create(path).map_err(|_| ContainerError::CannotSaveConfigurationFile)?;
+            fs::File::create(&tmp_path).map_err(|_| ContainerError::CannotSaveConfigurationFile)?;
 
-        Ok(serde_json::to_writer(file, self)
-            .map_err(|_| ContainerError::CannotSaveConfigurationFile)?)
+        serde_json::to_writer(&file, self)
+            .map_err(|_| ContainerError::CannotSaveConfigurationFile)?;
+        file.sync_all()
+            .map_err(|_| ContainerError::CannotSaveConfigurationFile)?;
+        fs::rename(&tmp_path, path).map_err(|_| ContainerError::CannotSaveConfigurationFile)
     }
 
     /// Retrieve the name of the container
@@ -111,6 +286,156 @@ impl ContainerConfig {
     pub fn image_name(&self) -> &str {
         &self.image_name
     }
+
+    /// Retrieve the digest of the manifest of the container's image, which keeps being used even
+    /// if the name of the image is given to another one, unless the container predates it
+    pub fn image_digest(&self) -> Option<&str> {
+        self.image_digest.as_deref()
+    }
+
+    /// Retrieve the networking mode of the container
+    pub fn network_mode(&self) -> NetworkMode {
+        self.network_mode
+    }
+
+    /// Retrieve the address of the container on the jocker bridge, if any
+    pub fn ip_address(&self) -> Option<Ipv4Addr> {
+        self.ip_address
+    }
+
+    /// Set the networking mode of the container
+    pub fn set_network_mode(&mut self, network_mode: NetworkMode) {
+        self.network_mode = network_mode;
+        if network_mode != NetworkMode::Bridge {
+            self.ip_address = None;
+        }
+    }
+
+    /// Retrieve the `KEY=VALUE` environment variables set for the container's processes
+    pub fn env(&self) -> &[String] {
+        &self.env
+    }
+
+    /// Set a `KEY=VALUE` environment variable for the container's processes
+    pub fn set_env(&mut self, variable: &str) {
+        set_environment_variable(&mut self.env, variable);
+    }
+
+    /// Retrieve the working directory of the container's processes, if not the root
+    pub fn working_dir(&self) -> Option<&Path> {
+        self.working_dir.as_deref()
+    }
+
+    /// Set the working directory of the container's processes
+    pub fn set_working_dir(&mut self, working_dir: Option<PathBuf>) {
+        self.working_dir = working_dir;
+    }
+
+    /// Retrieve the `USER[:GROUP]` the container's processes run as, if not root
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Set the `USER[:GROUP]` the container's processes run as
+    pub fn set_user(&mut self, user: Option<String>) {
+        self.user = user;
+    }
+
+    /// Use the default environment, working directory and user of the image of the container,
+    /// which the options of the container may then override
+    pub fn apply_image_defaults(&mut self, defaults: &ExecutionConfig) {
+        for variable in defaults.env() {
+            self.set_env(variable);
+        }
+        if let Some(working_dir) = defaults.working_dir() {
+            self.working_dir = Some(PathBuf::from(working_dir));
+        }
+        if let Some(user) = defaults.user() {
+            self.user = Some(user.to_string());
+        }
+    }
+
+    /// Check whether the container's processes are given a pseudo-terminal
+    pub fn tty(&self) -> bool {
+        self.tty
+    }
+
+    /// Set whether the container's processes are given a pseudo-terminal
+    pub fn set_tty(&mut self, tty: bool) {
+        self.tty = tty;
+    }
+
+    /// Check whether the standard input is passed to the container's pseudo-terminal
+    pub fn open_stdin(&self) -> bool {
+        self.open_stdin
+    }
+
+    /// Set whether the standard input is passed to the container's pseudo-terminal
+    pub fn set_open_stdin(&mut self, open_stdin: bool) {
+        self.open_stdin = open_stdin;
+    }
+
+    /// Check whether the command runs under an init process rather than as PID 1
+    pub fn init(&self) -> bool {
+        self.init
+    }
+
+    /// Set whether the command runs under an init process rather than as PID 1
+    pub fn set_init(&mut self, init: bool) {
+        self.init = init;
+    }
+
+    /// Check whether the container is removed once its command exits
+    pub fn auto_remove(&self) -> bool {
+        self.auto_remove
+    }
+
+    /// Set whether the container is removed once its command exits
+    pub fn set_auto_remove(&mut self, auto_remove: bool) {
+        self.auto_remove = auto_remove;
+    }
+
+    /// Retrieve the host paths mounted in the container
+    pub fn mounts(&self) -> &[BindMount] {
+        &self.mounts
+    }
+
+    /// Mount a host path in the container, replacing any other mount at the same location
+    pub fn add_mount(&mut self, mount: BindMount) {
+        self.mounts
+            .retain(|existing| existing.target() != mount.target());
+        self.mounts.push(mount);
+    }
+
+    /// Retrieve the limits on the resources the container can use
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    /// Retrieve a mutable reference to the limits on the resources the container can use
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+
+    /// Retrieve how the log of the container is rotated
+    pub fn log_config(&self) -> &LogConfig {
+        &self.log_config
+    }
+
+    /// Retrieve a mutable reference to how the log of the container is rotated
+    pub fn log_config_mut(&mut self) -> &mut LogConfig {
+        &mut self.log_config
+    }
+
+    /// Retrieve the runtime state of the container
+    pub fn state(&self) -> &ContainerState {
+        &self.state
+    }
+
+    /// Retrieve a mutable reference to the runtime state of the container
+    pub fn state_mut(&mut self) -> &mut ContainerState {
+        &mut self.state
+    }
 }
 
 /// Structure representing a container
@@ -118,6 +443,7 @@ impl ContainerConfig {
 pub struct Container {
     config: ContainerConfig,
     path: PathBuf,
+    window_sizes: Option<RawFd>,
 }
 
 impl Container {
@@ -125,17 +451,30 @@ impl Container {
     fn from_directory(path: PathBuf) -> Result<Self, ContainerError> {
         let config = ContainerConfig::load_from_file(&path.join("config.json"))?;
 
-        Ok(Self { config, path })
+        Ok(Self {
+            config,
+            path,
+            window_sizes: None,
+        })
     }
 
     /// Create a container from a directory containing an initialized container
-    pub fn create(name: String, path: PathBuf, image_name: String) -> Result<Self, ContainerError> {
+    pub fn create(
+        name: String,
+        path: PathBuf,
+        image_name: String,
+        image_digest: String,
+    ) -> Result<Self, ContainerError> {
         fs::create_dir_all(&path).map_err(ContainerError::CreationError)?;
-        let config = ContainerConfig::from(name, image_name);
+        let config = ContainerConfig::from(name, image_name, image_digest);
 
         config.save(&path.join("config.json"))?;
 
-        Ok(Self { config, path })
+        Ok(Self {
+            config,
+            path,
+            window_sizes: None,
+        })
     }
 
     /// Retrieve the name of the container
@@ -143,12 +482,107 @@ impl Container {
         self.config.name()
     }
 
+    /// Set the pipe from which the window sizes of the terminals attached to the container are
+    /// read, as encoded by [`tty::encode_window_size`], to apply them to its pseudo-terminal
+    pub fn set_window_sizes(&mut self, window_sizes: RawFd) {
+        self.window_sizes = Some(window_sizes);
+    }
+
+    /// Retrieve the configuration of the container
+    pub fn config(&self) -> &ContainerConfig {
+        &self.config
+    }
+
+    /// Retrieve a mutable reference to the configuration of the container
+    ///
+    /// Changes are only persisted by [`Container::save_config`].
+    pub fn config_mut(&mut self) -> &mut ContainerConfig {
+        &mut self.config
+    }
+
+    /// Persist the configuration of the container
+    pub fn save_config(&self) -> Result<(), ContainerError> {
+        self.config.save(&self.path.join("config.json"))
+    }
+
+    /// Reload the configuration of the container, whose state may be updated by other processes
+    pub fn reload_config(&mut self) -> Result<(), ContainerError> {
+        self.config = ContainerConfig::load_from_file(&self.path.join("config.json"))?;
+        Ok(())
+    }
+
+    /// Send a signal to the main process of the container
+    pub fn kill(&self, signal: Signal) -> Result<(), ContainerError> {
+        let state = self.config.state();
+
+        // The container is not running anymore if its identifier was reused by another process,
+        // which must not be signalled
+        match (state.status(), state.pid()) {
+            (ContainerStatus::Running, Some(pid)) => {
+                kill(pid, signal).map_err(ContainerError::ContainerExecutionError)
+            }
+            _ => Err(ContainerError::NotRunning),
+        }
+    }
+
+    /// Wait for the container to exit, for at most a given duration if there is one, and tell
+    /// whether it exited
+    fn wait_for_exit(&mut self, timeout: Option<Duration>) -> Result<bool, ContainerError> {
+        let start = Instant::now();
+
+        loop {
+            self.reload_config()?;
+            if self.config.state().status() != ContainerStatus::Running {
+                return Ok(true);
+            }
+            if timeout.map_or(false, |timeout| start.elapsed() >= timeout) {
+                return Ok(false);
+            }
+            std::thread::sleep(EXIT_POLL_INTERVAL);
+        }
+    }
+
+    /// Stop the container, asking it to terminate with SIGTERM and killing it if it is still
+    /// running after a given timeout
+    ///
+    /// Stopping a container which is not running does nothing.
+    pub fn stop(&mut self, timeout: Duration) -> Result<(), ContainerError> {
+        match self.kill(SIGTERM) {
+            Err(ContainerError::NotRunning) => return Ok(()),
+            result => result?,
+        }
+
+        if !self.wait_for_exit(Some(timeout))? {
+            match self.kill(SIGKILL) {
+                Err(ContainerError::NotRunning) => return Ok(()),
+                result => result?,
+            }
+            self.wait_for_exit(None)?;
+        }
+        Ok(())
+    }
+
     /// Retrieve the path to the container's directory
     pub fn path(&self) -> &Path {
         &self.path
     }
 
-    fn setup_overlay(&self, image: &ExtractedImage) -> Result<(), ContainerError> {
+    /// Retrieve the path to the log of the container's output
+    pub fn log_path(&self) -> PathBuf {
+        self.path.join("container.log")
+    }
+
+    /// Retrieve the path to the socket serving the standard streams of a detached container
+    pub fn attach_socket_path(&self) -> PathBuf {
+        self.path.join("attach.sock")
+    }
+
+    /// Mount the layers of an image under the container's read-write directory as its root
+    /// filesystem
+    ///
+    /// Unprivileged users have the overlay filesystem use `user.overlay.*` extended attributes,
+    /// as set on the layers they extract, instead of the `trusted.overlay.*` ones.
+    fn setup_overlay(&self, image: &ExtractedImage, rootless: bool) -> Result<(), ContainerError> {
         // Create the "upper directory" for the overlay filesystem
         let upper_dir_path = self.path.join("cow_rw");
         if !upper_dir_path.exists() {
@@ -169,16 +603,36 @@ impl Container {
             fs::create_dir(&rootfs_path).map_err(ContainerError::CreationError)?;
         }
 
+        // The mount options must fit in a page, so the layers are given through short symbolic
+        // links relative to the container's directory rather than through their full paths
+        let lower_links_path = self.path.join("lower");
+        if lower_links_path.exists() {
+            fs::remove_dir_all(&lower_links_path).map_err(ContainerError::CreationError)?;
+        }
+        fs::create_dir(&lower_links_path).map_err(ContainerError::CreationError)?;
+        for (i, layer_path) in image.layer_paths().iter().enumerate() {
+            symlink(layer_path, lower_links_path.join(i.to_string()))
+                .map_err(ContainerError::CreationError)?;
+        }
+        chdir(&self.path).map_err(|_| ContainerError::ContainerSetupError)?;
+
+        // The layers of the image are stacked from the top one to the bottom one
+        let lower_dirs = (0..image.layer_paths().len())
+            .map(|i| format!("lower/{}", i))
+            .collect::<Vec<_>>()
+            .join(":");
+
         mount(
             Some(Path::new("overlay")),
             &rootfs_path,
             Some(Path::new("overlay")),
             MsFlags::MS_SILENT,
             Some(Path::new(&format!(
-                "lowerdir={},upperdir={},workdir={}",
-                image.path().display(),
+                "lowerdir={},upperdir={},workdir={}{}",
+                lower_dirs,
                 upper_dir_path.display(),
                 work_dir_path.display(),
+                if rootless { ",userxattr" } else { "" },
             ))),
         )
         .map_err(|_| ContainerError::ContainerSetupError)
@@ -212,18 +666,35 @@ impl Container {
                 PathBuf::from("dev").join("pts"),
                 Some("devpts"),
                 MsFlags::MS_NOATIME,
-                None,
+                // A private instance, so that the container cannot access the terminals of the host
+                Some(Path::new("newinstance,ptmxmode=0666,mode=0620")),
             ),
         ];
 
         for (source, target, fstype, flags, data) in mounts.iter() {
-            mount::<Path, Path, Path, Path>(
+            let target = self.path.join("rootfs").join(target);
+            let result = mount::<Path, Path, Path, Path>(
                 *source,
-                &self.path.join("rootfs").join(target),
+                &target,
                 fstype.map(Path::new),
                 *flags,
                 *data,
-            )?;
+            );
+
+            match result {
+                // Mounting sysfs requires owning the network namespace, which is not the case
+                // for an unprivileged container sharing the network of the host
+                Err(nix::Error::Sys(Errno::EPERM)) if *fstype == Some("sysfs") => {
+                    mount::<Path, Path, Path, Path>(
+                        Some(Path::new("/sys")),
+                        &target,
+                        None,
+                        MsFlags::MS_BIND | MsFlags::MS_REC,
+                        None,
+                    )?
+                }
+                result => result?,
+            }
         }
 
         Ok(())
@@ -248,12 +719,38 @@ impl Container {
             let path = dev_path.join(name);
 
             if !path.exists() {
-                mknod(&path, *kind, rw_all, makedev(*major, *minor))?;
-                // Ensure the file's permissions are as expected (the umask could have restricted them)
-                fchmodat(None, &path, rw_all, FchmodatFlags::FollowSymlink)?;
+                match mknod(&path, *kind, rw_all, makedev(*major, *minor)) {
+                    // Ensure the file's permissions are as expected (the umask could have restricted them)
+                    Ok(()) => fchmodat(None, &path, rw_all, FchmodatFlags::FollowSymlink)?,
+                    // Devices cannot be created in a user namespace, create a mount point instead
+                    Err(nix::Error::Sys(Errno::EPERM)) => {
+                        fs::File::create(&path).map_err(|_| nix::Error::last())?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            // Bind-mount the device of the host over mount points
+            let is_device = fs::metadata(&path)
+                .map(|metadata| metadata.file_type().is_char_device())
+                .unwrap_or(false);
+            if !is_device {
+                mount::<Path, Path, Path, Path>(
+                    Some(&Path::new("/dev").join(name)),
+                    &path,
+                    None,
+                    MsFlags::MS_BIND,
+                    None,
+                )?;
             }
         }
 
+        // Allocate pseudo-terminals from the devpts instance of the container
+        let ptmx_path = dev_path.join("ptmx");
+        if fs::symlink_metadata(&ptmx_path).is_err() {
+            symlink("pts/ptmx", &ptmx_path).map_err(|_| nix::Error::last())?;
+        }
+
         Ok(())
     }
 
@@ -267,73 +764,316 @@ impl Container {
         Ok(())
     }
 
-    fn setup_cgroup(&self, group_name: &str) -> Result<(), Error> {
-        let jocker_cgroup_cpu_path = Path::new("/sys/fs/cgroup").join(group_name).join("jocker");
-        let container_cgroup_cpu_path = jocker_cgroup_cpu_path.join(&self.config.name());
+    /// Obtain a handle over the control groups of the container
+    fn cgroup(&self) -> Box<dyn Cgroup> {
+        cgroup::for_container(self.config.name())
+    }
+
+    /// Create the control groups of the container, apply its limits and move a process into them
+    fn setup_cgroup(&self, pid: Pid) -> Result<(), ContainerError> {
+        let cgroup = self.cgroup();
+
+        cgroup
+            .setup(self.config.resources())
+            .and_then(|_| cgroup.join(pid))
+            .map_err(ContainerError::CgroupSetupError)
+    }
+
+    /// Retrieve the manifest of the container's image, by its digest unless the container
+    /// predates it
+    fn image_manifest(&self, config: &Config) -> Result<Manifest, ImageError> {
+        let image_store = config.image_store();
 
-        if !container_cgroup_cpu_path.exists() {
-            fs::create_dir_all(&container_cgroup_cpu_path)?;
+        match self.config.image_digest() {
+            Some(digest) => image_store.manifest_from_digest(digest),
+            None => {
+                let image = image_store
+                    .get_image(&self.config.image_name)
+                    .ok_or(ImageError::InvalidImage)?;
+                image_store.manifest(&image)
+            }
         }
+    }
 
-        let mut tasks_file = fs::File::create(container_cgroup_cpu_path.join("tasks"))?;
-        tasks_file.write_fmt(format_args!("{}", getpid()))?;
+    /// Retrieve the configuration of the container's image
+    pub fn image_config(&self, config: &Config) -> Result<ImageConfig, ContainerError> {
+        self.image_manifest(config)
+            .and_then(|manifest| config.image_store().config(&manifest))
+            .map_err(ContainerError::InitializationError)
+    }
 
-        Ok(())
+    fn extract_image(&self, config: &Config) -> Result<ExtractedImage, ContainerError> {
+        self.image_manifest(config)
+            .and_then(|manifest| config.image_store().extract_image(&manifest))
+            .map_err(ContainerError::InitializationError)
+    }
+
+    /// Configure the network of a freshly cloned container process, according to its mode
+    fn setup_network(&mut self, config: &Config, pid: Pid) -> Result<(), ContainerError> {
+        match self.config.network_mode {
+            NetworkMode::Host => Ok(()),
+            NetworkMode::None => {
+                network::setup_loopback(pid).map_err(ContainerError::NetworkSetupError)
+            }
+            // Creating interfaces on the host requires privileges
+            NetworkMode::Bridge if userns::is_rootless() => Err(ContainerError::NetworkSetupError(
+                NetworkError::BridgeRequiresPrivileges,
+            )),
+            NetworkMode::Bridge => {
+                let address = match self.config.ip_address {
+                    Some(address) => address,
+                    None => {
+                        let (address, _lock) = network::allocate_address(&config.container_store())
+                            .map_err(ContainerError::NetworkSetupError)?;
+                        // The address is only reserved once it is persisted
+                        self.config.ip_address = Some(address);
+                        self.save_config()?;
+                        address
+                    }
+                };
+
+                let result: Result<(), NetworkError> = try {
+                    network::setup_bridge()?;
+                    network::setup_loopback(pid)?;
+                    network::setup_veth_pair(pid, address)?
+                };
+                result.map_err(ContainerError::NetworkSetupError)
+            }
+        }
     }
 
-    fn setup_memory_cgroup(&self) -> Result<(), Error> {
-        self.setup_cgroup("memory")?;
+    /// Compute the environment of a process executed in the container as a given user
+    fn process_environment(&self, user: &User) -> Vec<String> {
+        let mut env = vec![
+            format!("PATH={}", DEFAULT_PATH),
+            format!("HOSTNAME={}", self.config.name()),
+            format!("HOME={}", user.home().display()),
+        ];
+
+        for variable in self.config.env() {
+            set_environment_variable(&mut env, variable);
+        }
+        env
+    }
 
-        // TODO: memory limit, swap size, swappiness
-        Ok(())
+    /// Find a program in the `PATH` of an environment, like `execvp` would in the current one
+    fn find_program(program: &str, env: &[String]) -> Option<PathBuf> {
+        if program.contains('/') {
+            return Some(PathBuf::from(program));
+        }
+
+        let path = env
+            .iter()
+            .find(|variable| variable.starts_with("PATH="))
+            .map(|variable| &variable["PATH=".len()..])
+            .unwrap_or(DEFAULT_PATH);
+
+        path.split(':')
+            .map(|dir| Path::new(dir).join(program))
+            .find(|candidate| {
+                fs::metadata(candidate)
+                    .map(|metadata| metadata.is_file() && metadata.mode() & 0o111 != 0)
+                    .unwrap_or(false)
+            })
     }
 
-    fn setup_cpu_cgroup(&self) -> Result<(), Error> {
-        self.setup_cgroup("cpu")?;
+    /// Replace the current process, already isolated in the container, with the given command
+    /// running as the configured user, in the configured directory and environment
+    ///
+    /// If the jocker executable is given, it runs the command in `init` mode instead.
+    fn exec_process(&self, args: &[String], init: Option<RawFd>) -> Result<(), Error> {
+        let root = Path::new("/");
+        let user = User::resolve(self.config.user().unwrap_or("0"), root)?;
+        let env = self.process_environment(&user);
+
+        // Create the working directory while still being root
+        let working_dir = self.config.working_dir().unwrap_or(root);
+        fs::create_dir_all(working_dir)
+            .with_context(|_| format_err!("cannot create the working directory"))?;
+
+        // Supplementary groups cannot be set if setgroups(2) is denied in our user namespace
+        let setgroups_allowed = fs::read_to_string("/proc/self/setgroups")
+            .map(|content| content.trim() != "deny")
+            .unwrap_or(true);
+
+        let program = Self::find_program(&args[0], &env)
+            .ok_or_else(|| format_err!("{}: command not found", args[0]))?;
+        let c_program = CString::new(program.as_os_str().as_bytes())?;
+        let c_args = args
+            .iter()
+            .map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let c_env = env
+            .into_iter()
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(init_fd) = init {
+            // The init process keeps the privileges of the container, only the command it
+            // runs switches to the user
+            let mut init_args = vec![
+                "jocker".to_string(),
+                "init".to_string(),
+                "--uid".to_string(),
+                user.uid().to_string(),
+                "--gid".to_string(),
+                user.gid().to_string(),
+            ];
+            if setgroups_allowed {
+                let groups: Vec<String> = user.groups().iter().map(Gid::to_string).collect();
+                init_args.push("--groups".to_string());
+                init_args.push(groups.join(","));
+            }
+            init_args.push("--".to_string());
+            let c_init_args = init_args
+                .into_iter()
+                .map(CString::new)
+                .chain(std::iter::once(Ok(c_program)))
+                .chain(c_args.into_iter().map(Ok))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            chdir(working_dir)?;
+            return match fexecve(init_fd, &c_init_args, &c_env) {
+                Err(nix::Error::Sys(Errno::ENOENT)) => Err(format_err!(
+                    "cannot run the init process: the jocker executable must be statically linked"
+                )),
+                Err(e) => Err(e.into()),
+                Ok(_) => Ok(()),
+            };
+        }
 
-        // TODO: CPU shares, CPU number, allowed CPUs
+        if setgroups_allowed {
+            setgroups(user.groups())?;
+        }
+        setgid(user.gid())?;
+        setuid(user.uid())?;
+        chdir(working_dir)?;
+        execve(&c_program, &c_args, &c_env)?;
         Ok(())
     }
 
-    fn extract_image(&self, config: &Config) -> Result<ExtractedImage, ContainerError> {
-        let extracted_image_store = config.extracted_image_store();
+    /// Allocate a pseudo-terminal, send its master through a socket and make its slave the
+    /// controlling terminal of the current process, and the console of the container if
+    /// `console` is set
+    fn setup_tty(socket: RawFd, console: bool) -> Result<(), Error> {
+        let (master, slave, slave_path) = tty::open_pty()?;
+        tty::send_fd(socket, master)?;
+        close(master)?;
+        close(socket)?;
+
+        if console {
+            tty::mount_console(&slave_path)
+                .with_context(|_| format_err!("cannot mount the console"))?;
+        }
+        tty::set_controlling_terminal(slave)?;
+        Ok(())
+    }
+
+    /// Receive the master of a pseudo-terminal allocated by [`Container::setup_tty`] and proxy
+    /// it to the host terminal, passing the standard input if `open_stdin` is set
+    ///
+    /// The host terminal is in raw mode until the returned guard is dropped.
+    fn proxy_tty(
+        socket: RawFd,
+        open_stdin: bool,
+        window_sizes: Option<RawFd>,
+        logger: Option<LineLogger>,
+    ) -> (Option<JoinHandle<()>>, Option<RawMode>) {
+        // The container does not send anything if its setup fails, in which case it exits
+        let master = tty::receive_fd(socket);
+        let _ = close(socket);
+        let master = match master {
+            Ok(master) => master,
+            Err(_) => return (None, None),
+        };
 
-        if let Some(image) = extracted_image_store.get_extracted_image(&self.config.image_name()) {
-            Ok(image)
+        // Keys are passed untouched to the pseudo-terminal, which interprets them instead
+        let raw_mode = if open_stdin {
+            RawMode::enable(0).unwrap_or_else(|e| {
+                eprintln!("warning: {}", e);
+                None
+            })
         } else {
-            let image_store = config.image_store();
-            let image = image_store
-                .get_image(&self.config.image_name)
-                .ok_or(ImageError::InvalidImage)
-                .map_err(ContainerError::InitializationError)?;
+            None
+        };
+        let input = if open_stdin { Some(0) } else { None };
 
-            Ok(image
-                .extract_to(extracted_image_store.path().join(image.name()))
-                .map_err(ContainerError::InitializationError)?)
-        }
+        (
+            Some(tty::proxy(master, input, window_sizes, logger)),
+            raw_mode,
+        )
     }
 
-    /// Execute a command in the container
-    pub fn run_command(&self, config: &Config, command: &str) -> Result<(), ContainerError> {
+    /// Execute a command in the container, given as the program to run followed by its arguments
+    pub fn run_command(&mut self, config: &Config, args: &[String]) -> Result<(), ContainerError> {
+        if args.is_empty() || args.iter().any(|arg| arg.contains('\0')) {
+            return Err(ContainerError::InvalidCommand);
+        }
+
         let image = self.extract_image(config)?;
-        let c_args = [
-            CString::new("/bin/sh").unwrap(),
-            CString::new("-c").unwrap(),
-            CString::new(command).map_err(|_| ContainerError::InvalidCommand)?,
-        ];
 
         const STACK_SIZE: usize = 1024 * 1024;
         let ref mut stack: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
+        // Pipe used to hold the container back until the parent is done configuring it
+        let (sync_read, sync_write) = pipe().map_err(ContainerError::ContainerExecutionError)?;
+
+        // Pipes through which the output of the container is captured into its log
+        let log_writer = LogWriter::open(self.log_path(), self.config.log_config.clone())
+            .map_err(ContainerError::LoggingError)?;
+        let (stdout_read, stdout_write) =
+            pipe().map_err(ContainerError::ContainerExecutionError)?;
+        let (stderr_read, stderr_write) =
+            pipe().map_err(ContainerError::ContainerExecutionError)?;
+
+        // Socket through which the container sends the master of its pseudo-terminal
+        let tty_sockets = if self.config.tty() {
+            Some(
+                socketpair(
+                    AddressFamily::Unix,
+                    SockType::Stream,
+                    None,
+                    SockFlag::SOCK_CLOEXEC,
+                )
+                .map_err(ContainerError::ContainerExecutionError)?,
+            )
+        } else {
+            None
+        };
+
+        // The jocker executable is opened beforehand, since it is out of reach once in the container
+        let init = if self.config.init() {
+            Some(
+                fs::File::open("/proc/self/exe")
+                    .map_err(|_| ContainerError::ContainerExecutionError(nix::Error::last()))?,
+            )
+        } else {
+            None
+        };
+
+        let rootless = userns::is_rootless();
+        let this = &*self;
         let run_container = move || {
             let result: Result<(), Error> = try {
-                // Setup control groups
-                self.setup_cpu_cgroup()
-                    .with_context(|_| format_err!("cannot setup a CPU cgroup"))?;
-                self.setup_memory_cgroup()
-                    .with_context(|_| format_err!("cannot setup a memory cgroup"))?;
+                // Send the output to the log, including errors happening while setting up
+                for (pipe_write, fd) in &[(stdout_write, 1), (stderr_write, 2)] {
+                    dup2(*pipe_write, *fd)?;
+                }
+                for fd in &[stdout_read, stdout_write, stderr_read, stderr_write] {
+                    close(*fd)?;
+                }
+                if let Some((parent_socket, _)) = tty_sockets {
+                    close(parent_socket)?;
+                }
 
-                sethostname(self.config.name())?;
+                // Wait for the parent to configure the namespaces from the outside
+                close(sync_write)?;
+                if read(sync_read, &mut [0])? != 1 {
+                    std::process::exit(242);
+                }
+                close(sync_read)?;
+
+                sethostname(this.config.name())?;
 
                 mount::<Path, Path, Path, Path>(
                     None,
@@ -345,20 +1085,27 @@ impl Container {
                 .with_context(|_| format_err!("cannot mount"))?;
 
                 // Setup OverlayFS with the image directory under an empty read-write directory
-                self.setup_overlay(&image).with_context(|_| {
+                this.setup_overlay(&image, rootless).with_context(|_| {
                     format_err!("cannot setup the container's root filesystem")
                 })?;
 
                 // Mount basic filesystems (procfs, sysfs, etc)
-                self.mount_kernel_filesystems()
+                this.mount_kernel_filesystems()
                     .with_context(|_| format_err!("cannot mount kernel-related filesystems"))?;
 
                 // Create basic devices (/dev/{null,zero,urandom}, etc)
-                self.create_devices()
+                this.create_devices()
                     .with_context(|_| format_err!("cannot create devices"))?;
 
+                // Mount the volumes over the root filesystem
+                for mount in this.config.mounts() {
+                    mount
+                        .mount_in(&this.path.join("rootfs"), &config.volume_store())
+                        .with_context(|_| format_err!("cannot mount volumes"))?;
+                }
+
                 // Chroot and change directory to isolate the container
-                self.move_to_new_root()
+                this.move_to_new_root()
                     .with_context(|_| format_err!("cannot move to new root"))?;
 
                 // Detach the old root and remove it
@@ -368,19 +1115,17 @@ impl Container {
                 fs::remove_dir(&old_root)
                     .with_context(|_| format_err!("cannot remove the old root"))?;
 
+                // Allocate the pseudo-terminal of the container, proxied by the parent
+                if let Some((_, child_socket)) = tty_sockets {
+                    Self::setup_tty(child_socket, true)?;
+                }
+
                 // Execute the contained process
-                execv(&c_args[0], &c_args)?;
+                this.exec_process(args, init.as_ref().map(AsRawFd::as_raw_fd))?;
             };
 
             if let Err(ref e) = result {
-                let fail = e.as_fail();
-                eprint!("error: {}", fail);
-                for cause in fail.iter_causes() {
-                    eprint!(": {}", cause);
-                }
-                eprintln!();
-
-                std::process::exit(242);
+                exit_with_setup_error(e);
             }
             0
         };
@@ -389,77 +1134,282 @@ impl Container {
         let pid = clone(
             Box::new(run_container),
             stack,
-            CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWUTS | CloneFlags::CLONE_NEWNS,
+            CloneFlags::CLONE_NEWPID
+                | CloneFlags::CLONE_NEWUTS
+                | CloneFlags::CLONE_NEWNS
+                | self.config.network_mode.clone_flags()
+                | if rootless {
+                    CloneFlags::CLONE_NEWUSER
+                } else {
+                    CloneFlags::empty()
+                },
             Some(SIGCHLD as i32),
         )
         .map_err(ContainerError::ContainerExecutionError)?;
+        close(sync_read).map_err(ContainerError::ContainerExecutionError)?;
+        close(stdout_write).map_err(ContainerError::ContainerExecutionError)?;
+        close(stderr_write).map_err(ContainerError::ContainerExecutionError)?;
+        if let Some((_, child_socket)) = tty_sockets {
+            close(child_socket).map_err(ContainerError::ContainerExecutionError)?;
+        }
 
-        let status = waitpid(pid, None).map_err(ContainerError::ContainerExecutionError)?;
+        let log_writer = Arc::new(Mutex::new(log_writer));
+        let captures = vec![
+            logs::capture(stdout_read, LogStream::Stdout, log_writer.clone()),
+            logs::capture(stderr_read, LogStream::Stderr, log_writer.clone()),
+        ];
+        // The captures end once the container and all its processes have exited
+        let wait_for_captures = || {
+            for capture in captures {
+                let _ = capture.join();
+            }
+        };
+
+        self.config.state_mut().set_running(pid);
+        let save_result = forward_signals_to(pid)
+            .map_err(ContainerError::ContainerExecutionError)
+            .and_then(|_| self.save_config());
+
+        // Configure the user namespace or the control groups, which unprivileged users are not
+        // allowed to manage, and the network, then let the container proceed
+        let setup_result = if rootless {
+            userns::setup_id_mappings(pid).map_err(ContainerError::UserNamespaceSetupError)
+        } else {
+            self.setup_cgroup(pid)
+        }
+        .and_then(|_| save_result)
+        .and_then(|_| self.setup_network(config, pid));
+        if let Err(e) = setup_result {
+            if let Some((parent_socket, _)) = tty_sockets {
+                close(parent_socket).map_err(ContainerError::ContainerExecutionError)?;
+            }
+            close(sync_write).map_err(ContainerError::ContainerExecutionError)?;
+            kill(pid, SIGKILL).map_err(ContainerError::ContainerExecutionError)?;
+            let status = wait_for_child(pid)?;
+            stop_forwarding_signals();
+            wait_for_captures();
+            self.cgroup().remove();
+            self.config
+                .state_mut()
+                .set_exited(state::exit_code(status), false, false);
+            self.save_config()?;
+            return Err(e);
+        }
+        write(sync_write, &[0]).map_err(ContainerError::ContainerExecutionError)?;
+        close(sync_write).map_err(ContainerError::ContainerExecutionError)?;
+
+        let terminal = tty_sockets.map(|(parent_socket, _)| {
+            let logger = LineLogger::new(log_writer, LogStream::Stdout);
+            Self::proxy_tty(
+                parent_socket,
+                self.config.open_stdin(),
+                self.window_sizes,
+                Some(logger),
+            )
+        });
+
+        let status = wait_for_child(pid)?;
+        stop_forwarding_signals();
+        wait_for_captures();
+        // The terminal is restored once the container no longer uses its pseudo-terminal
+        if let Some((proxy, raw_mode)) = terminal {
+            let _ = proxy.map(JoinHandle::join);
+            drop(raw_mode);
+        }
+        let cgroup = self.cgroup();
+        let oom_killed = cgroup.oom_killed();
+        let pids_limit_reached = cgroup.pids_limit_reached();
+        cgroup.remove();
+
+        self.config.state_mut().set_exited(
+            state::exit_code(status),
+            oom_killed,
+            pids_limit_reached,
+        );
+        self.save_config()?;
 
         match status {
             WaitStatus::Exited(_, 0) => Ok(()),
+            _ if oom_killed => Err(ContainerError::OutOfMemory),
+            _ if pids_limit_reached => Err(ContainerError::PidsLimitReached),
             WaitStatus::Exited(_, 242) => Err(ContainerError::ContainerSetupError),
             WaitStatus::Exited(_, result) => Err(ContainerError::CommandExitedWithError(result)),
             _ => Err(ContainerError::ContainerExitedAbnormally),
         }
     }
 
-    /// Append files recursively, ignoring special files
-    fn append_files<T: Write>(
+    /// Open the namespaces of the main process of the container to join, in the order in which
+    /// they have to be joined
+    fn open_namespaces(&self, pid: Pid) -> Result<Vec<(fs::File, CloneFlags)>, Error> {
+        let mut namespaces = vec![];
+
+        // Joining the user namespace first gives the privileges needed to join the others
+        if userns::is_rootless() {
+            namespaces.push(("user", CloneFlags::CLONE_NEWUSER));
+        }
+        namespaces.push(("uts", CloneFlags::CLONE_NEWUTS));
+        if self
+            .config
+            .network_mode()
+            .clone_flags()
+            .contains(CloneFlags::CLONE_NEWNET)
+        {
+            namespaces.push(("net", CloneFlags::CLONE_NEWNET));
+        }
+        namespaces.push(("pid", CloneFlags::CLONE_NEWPID));
+        namespaces.push(("mnt", CloneFlags::CLONE_NEWNS));
+
+        namespaces
+            .into_iter()
+            .map(|(name, flag)| {
+                let path = format!("/proc/{}/ns/{}", pid, name);
+                let file = fs::File::open(&path)
+                    .with_context(|_| format_err!("cannot open namespace {}", path))?;
+                Ok((file, flag))
+            })
+            .collect()
+    }
+
+    /// Execute an additional command in the running container, given as the program to run
+    /// followed by its arguments
+    ///
+    /// Unless `interactive` is set, the command does not read from the standard input. If `tty`
+    /// is set, the command is given a pseudo-terminal.
+    pub fn exec_command(
         &self,
-        tar: &mut tar::Builder<T>,
-        src_path: &Path,
-    ) -> Result<(), std::io::Error> {
-        let mut stack = vec![(src_path.to_path_buf(), true)];
-
-        while let Some((src, is_dir)) = stack.pop() {
-            let dest = src.strip_prefix(&src_path).unwrap().to_path_buf();
-            if is_dir {
-                for entry in fs::read_dir(&src)? {
-                    let entry = entry?;
-                    let file_type = entry.file_type()?;
-                    if file_type.is_file() || file_type.is_dir() || file_type.is_symlink() {
-                        stack.push((entry.path(), file_type.is_dir()));
+        args: &[String],
+        interactive: bool,
+        tty: bool,
+    ) -> Result<(), ContainerError> {
+        if args.is_empty() || args.iter().any(|arg| arg.contains('\0')) {
+            return Err(ContainerError::InvalidCommand);
+        }
+
+        let state = self.config.state();
+        let container_pid = match (state.status(), state.pid()) {
+            (ContainerStatus::Running, Some(pid)) => pid,
+            _ => return Err(ContainerError::NotRunning),
+        };
+
+        let tty_sockets = if tty {
+            Some(
+                socketpair(
+                    AddressFamily::Unix,
+                    SockType::Stream,
+                    None,
+                    SockFlag::SOCK_CLOEXEC,
+                )
+                .map_err(ContainerError::ContainerExecutionError)?,
+            )
+        } else {
+            None
+        };
+
+        // Pipe through which a failure to set up the command is reported, instead of through an
+        // exit code the command itself could use, and which is closed once the command runs
+        let (error_read, error_write) =
+            pipe2(OFlag::O_CLOEXEC).map_err(ContainerError::ContainerExecutionError)?;
+
+        // The intermediate process joins the namespaces, which only applies to its children
+        // for the PID namespace, and forks the process executing the command
+        let pid = match fork().map_err(ContainerError::ContainerExecutionError)? {
+            ForkResult::Parent { child } => child,
+            ForkResult::Child => {
+                let result: Result<(), Error> = try {
+                    close(error_read)?;
+                    if let Some((parent_socket, _)) = tty_sockets {
+                        close(parent_socket)?;
                     }
+                    if !userns::is_rootless() {
+                        self.cgroup()
+                            .join(getpid())
+                            .with_context(|_| format_err!("cannot join the control groups"))?;
+                    }
+
+                    for (file, flag) in self.open_namespaces(container_pid)? {
+                        setns(file.as_raw_fd(), flag)
+                            .with_context(|_| format_err!("cannot join a namespace"))?;
+                    }
+
+                    match fork()? {
+                        ForkResult::Parent { child } => {
+                            close(error_write)?;
+                            let status = wait_for_child(child)?;
+                            std::process::exit(state::exit_code(status).unwrap_or(255));
+                        }
+                        ForkResult::Child => {
+                            if let Some((_, child_socket)) = tty_sockets {
+                                Self::setup_tty(child_socket, false)?;
+                            } else if !interactive {
+                                let null_fd =
+                                    open(Path::new("/dev/null"), OFlag::O_RDONLY, Mode::empty())?;
+                                dup2(null_fd, 0)?;
+                                close(null_fd)?;
+                            }
+                            self.exec_process(args, None)?;
+                        }
+                    }
+                };
+
+                if let Err(ref e) = result {
+                    let _ = write(error_write, &[1]);
+                    exit_with_setup_error(e);
                 }
-                if dest != Path::new("") {
-                    tar.append_dir(&dest, &src)?;
-                }
-            } else {
-                tar.append_path_with_name(&src, &dest)?;
+                std::process::exit(0);
             }
+        };
+        let _ = close(error_write);
+
+        let terminal = tty_sockets.map(|(parent_socket, child_socket)| {
+            let _ = close(child_socket);
+            Self::proxy_tty(parent_socket, interactive, None, None)
+        });
+
+        let status = wait_for_child(pid)?;
+        if let Some((proxy, raw_mode)) = terminal {
+            let _ = proxy.map(JoinHandle::join);
+            drop(raw_mode);
+        }
+        let setup_failed = read(error_read, &mut [0]) == Ok(1);
+        let _ = close(error_read);
+
+        match status {
+            _ if setup_failed => Err(ContainerError::ContainerSetupError),
+            WaitStatus::Exited(_, 0) => Ok(()),
+            WaitStatus::Exited(_, result) => Err(ContainerError::CommandExitedWithError(result)),
+            _ => Err(ContainerError::ContainerExitedAbnormally),
         }
-        Ok(())
     }
 
-    /// Export the container as an image
+    /// Export the container as an image, made of a layer with the changes the container made
+    /// on top of the layers of its image
     pub fn export_as_image(&self, config: &Config, name: String) -> Result<Image, ContainerError> {
-        let image = self.extract_image(config)?;
-
-        self.setup_overlay(&image)?;
+        let image_store = config.image_store();
+        let base_manifest = self
+            .image_manifest(config)
+            .map_err(ContainerError::ExportError)?;
 
-        let rootfs_path = self.path.join("rootfs");
-        let temp_archive_path = Path::new("/tmp/image.tar.gz");
+        let upper_dir_path = self.path.join("cow_rw");
+        let archive_path = self.path.join("layer.tar.gz");
 
         let archive_result: Result<_, std::io::Error> = try {
-            // Build an archive with the container's filesystem tree
-            let tar_gz = fs::File::create(temp_archive_path)?;
+            // Build an archive with the upper directory, holding the changes to the image
+            let tar_gz = fs::File::create(&archive_path)?;
             let enc = GzEncoder::new(tar_gz, Compression::default());
             let mut tar = tar::Builder::new(enc);
             tar.follow_symlinks(false);
-            self.append_files(&mut tar, &rootfs_path)?
+            if upper_dir_path.exists() {
+                layer::append_diff(&mut tar, &upper_dir_path)?;
+            }
+            tar.into_inner()?.finish()?;
         };
         archive_result.map_err(ContainerError::ArchiveError)?;
 
-        // Unmount the container's filesystem
-        umount(&rootfs_path).map_err(|_| ContainerError::ContainerSetupError)?;
-
         // Create an image from the archive
-        let image_store = config.image_store();
         let image = image_store
-            .import_image(name, temp_archive_path)
+            .add_layer(name, &archive_path, Some(base_manifest))
             .map_err(ContainerError::ExportError)?;
-        fs::remove_file(temp_archive_path).map_err(ContainerError::ArchiveError)?;
+        fs::remove_file(&archive_path).map_err(ContainerError::ArchiveError)?;
 
         Ok(image)
     }
@@ -493,15 +1443,30 @@ impl<'a> ContainerStore<'a> {
         }))
     }
 
-    /// Create a container with a name and a base image
+    /// Create a container with a name and a base image, given by its name and the digest of
+    /// its manifest
     pub fn create_container(
         &self,
         name: String,
         image_name: String,
+        image_digest: String,
     ) -> Result<Container, ContainerError> {
         let path = self.containers_dir.join(&name);
 
-        Container::create(name, path, image_name)
+        Container::create(name, path, image_name, image_digest)
+    }
+
+    /// Collect the digests of the manifests of the images of the containers in this store
+    pub fn image_digests(&self) -> Result<Vec<String>, ContainerError> {
+        let mut digests = Vec::new();
+
+        for container in self
+            .containers()
+            .map_err(ContainerError::InvalidContainerDirectory)?
+        {
+            digests.extend(container?.config().image_digest().map(String::from));
+        }
+        Ok(digests)
     }
 
     /// Get a handle over a specific container in this store
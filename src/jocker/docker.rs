@@ -0,0 +1,278 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path};
+
+use failure::Fail;
+use serde_derive::Deserialize;
+use tar::{Archive, EntryType};
+
+use super::blob::BlobError;
+use super::image::{Descriptor, ImageConfig, ImageError, ImageStore, CONFIG_MEDIA_TYPE};
+use super::layer;
+
+/// Name of the file describing the images of an archive
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Name of the file giving the tags of the images of an archive, in older archives
+const REPOSITORIES_FILE: &str = "repositories";
+
+/// Maximum number of symbolic links followed to find a file of an archive
+const MAX_SYMLINKS: usize = 8;
+
+/// Error type for errors related to `docker save` archives
+#[derive(Fail, Debug)]
+pub enum DockerError {
+    /// The archive is missing a file, or its files do not match its manifest
+    #[fail(display = "invalid archive: {}", _0)]
+    InvalidArchive(String),
+
+    /// The manifest or the configuration of an image of the archive could not be parsed
+    #[fail(display = "invalid archive metadata: {}", _0)]
+    InvalidMetadata(serde_json::Error),
+
+    /// The archive has several images, none of them with the requested name
+    #[fail(display = "several images in the archive, none tagged {}", _0)]
+    AmbiguousImage(String),
+
+    /// The archive could not be read
+    #[fail(display = "unable to read archive: {}", _0)]
+    CannotReadArchive(std::io::Error),
+
+    /// A blob of an image could not be stored
+    #[fail(display = "{}", _0)]
+    BlobError(BlobError),
+
+    /// An image could not be stored
+    #[fail(display = "{}", _0)]
+    ImageError(ImageError),
+}
+
+/// Description of an image in the manifest of an archive
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ManifestEntry {
+    config: String,
+    #[serde(default)]
+    repo_tags: Option<Vec<String>>,
+    layers: Vec<String>,
+}
+
+/// Normalize the path of a file of an archive, relatively to its root
+fn normalize_path(path: &Path) -> Option<String> {
+    let mut components = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                components.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(components.join("/"))
+}
+
+/// Iterate over the regular files and the symbolic links of an archive, with their normalized
+/// path
+fn for_each_entry<F>(path: &Path, mut f: F) -> Result<(), DockerError>
+where
+    F: FnMut(&str, &mut tar::Entry<Box<dyn Read>>) -> Result<(), DockerError>,
+{
+    let file = fs::File::open(path).map_err(DockerError::CannotReadArchive)?;
+    let reader = layer::decompress(file).map_err(DockerError::CannotReadArchive)?;
+    let mut archive = Archive::new(reader);
+
+    for entry in archive.entries().map_err(DockerError::CannotReadArchive)? {
+        let mut entry = entry.map_err(DockerError::CannotReadArchive)?;
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Symlink => {}
+            _ => continue,
+        }
+        let entry_path = entry.path().map_err(DockerError::CannotReadArchive)?;
+        if let Some(entry_path) = normalize_path(&entry_path) {
+            f(&entry_path, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the whole content of an entry of an archive
+fn read_entry<R: Read>(entry: &mut R) -> Result<Vec<u8>, DockerError> {
+    let mut content = Vec::new();
+
+    entry
+        .read_to_end(&mut content)
+        .map_err(DockerError::CannotReadArchive)?;
+    Ok(content)
+}
+
+/// Import the images of an archive created by `docker save`, and return their names
+///
+/// Every image is named after all its tags. The given name is also given to the image of the
+/// archive if it has only one, and must otherwise be one of the tags of the archive. The layers
+/// are compressed and applied in order, their whiteout files hiding the files of the lower ones.
+pub fn import_archive(
+    image_store: &ImageStore,
+    name: String,
+    path: &Path,
+) -> Result<Vec<String>, DockerError> {
+    // The entries of an archive can only be read in order, so the manifest is read first to know
+    // what the other entries are
+    let mut manifest = None;
+    let mut repositories = None;
+    let mut symlinks = HashMap::new();
+    for_each_entry(path, |entry_path, entry| {
+        if entry.header().entry_type() == EntryType::Symlink {
+            let target = entry
+                .link_name()
+                .map_err(DockerError::CannotReadArchive)?
+                .ok_or_else(|| {
+                    DockerError::InvalidArchive(format!("invalid link {}", entry_path))
+                })?;
+            let parent_path = Path::new(entry_path)
+                .parent()
+                .unwrap_or_else(|| Path::new(""));
+            if let Some(target) = normalize_path(&parent_path.join(target)) {
+                symlinks.insert(entry_path.to_string(), target);
+            }
+        } else if entry_path == MANIFEST_FILE {
+            manifest = Some(read_entry(entry)?);
+        } else if entry_path == REPOSITORIES_FILE {
+            repositories = Some(read_entry(entry)?);
+        }
+        Ok(())
+    })?;
+
+    let manifest = manifest
+        .ok_or_else(|| DockerError::InvalidArchive(format!("missing {}", MANIFEST_FILE)))?;
+    let manifest: Vec<ManifestEntry> =
+        serde_json::from_slice(&manifest).map_err(DockerError::InvalidMetadata)?;
+    let repositories: HashMap<String, HashMap<String, String>> = match repositories {
+        Some(repositories) => {
+            serde_json::from_slice(&repositories).map_err(DockerError::InvalidMetadata)?
+        }
+        None => HashMap::new(),
+    };
+
+    let resolve = |entry_path: &str| -> Result<String, DockerError> {
+        let mut resolved = normalize_path(Path::new(entry_path))
+            .ok_or_else(|| DockerError::InvalidArchive(format!("invalid path {}", entry_path)))?;
+        for _ in 0..MAX_SYMLINKS {
+            match symlinks.get(&resolved) {
+                Some(target) => resolved = target.clone(),
+                None => return Ok(resolved),
+            }
+        }
+        Err(DockerError::InvalidArchive(format!(
+            "too many links to {}",
+            entry_path
+        )))
+    };
+
+    // Older archives only list the tags of the images in the repositories file, by the
+    // identifier of their top layer
+    let mut images = Vec::new();
+    for entry in &manifest {
+        let mut names = entry.repo_tags.clone().unwrap_or_else(|| {
+            let top_layer_id = entry
+                .layers
+                .last()
+                .and_then(|layer| Path::new(layer).parent())
+                .and_then(Path::to_str)
+                .unwrap_or("");
+            let mut names = Vec::new();
+            for (repository, tags) in &repositories {
+                for (tag, layer_id) in tags {
+                    if layer_id == top_layer_id {
+                        names.push(format!("{}:{}", repository, tag));
+                    }
+                }
+            }
+            names
+        });
+        if manifest.len() == 1 && !names.contains(&name) {
+            names.push(name.clone());
+        }
+        if names.is_empty() {
+            eprintln!(
+                "warning: skipping the untagged image with configuration {}",
+                entry.config
+            );
+            continue;
+        }
+
+        let config_path = resolve(&entry.config)?;
+        let layer_paths = entry
+            .layers
+            .iter()
+            .map(|layer| resolve(layer))
+            .collect::<Result<Vec<_>, _>>()?;
+        images.push((names, config_path, layer_paths));
+    }
+    if !images.iter().any(|(names, _, _)| names.contains(&name)) {
+        return Err(DockerError::AmbiguousImage(name));
+    }
+
+    let config_paths: HashSet<_> = images.iter().map(|(_, path, _)| path.clone()).collect();
+    let layer_paths: HashSet<_> = images
+        .iter()
+        .flat_map(|(_, _, paths)| paths.iter().cloned())
+        .collect();
+    let mut configs = HashMap::new();
+    let mut layers = HashMap::new();
+    for_each_entry(path, |entry_path, entry| {
+        if config_paths.contains(entry_path) {
+            configs.insert(entry_path.to_string(), read_entry(entry)?);
+        } else if layer_paths.contains(entry_path) && !layers.contains_key(entry_path) {
+            let reader = layer::decompress(entry).map_err(DockerError::CannotReadArchive)?;
+            let layer = image_store
+                .put_layer(reader)
+                .map_err(DockerError::ImageError)?;
+            layers.insert(entry_path.to_string(), layer);
+        }
+        Ok(())
+    })?;
+
+    let mut imported_names = Vec::new();
+    for (names, config_path, layer_paths) in images {
+        let config = configs
+            .get(&config_path)
+            .ok_or_else(|| DockerError::InvalidArchive(format!("missing {}", config_path)))?;
+        let image_config: ImageConfig =
+            serde_json::from_slice(config).map_err(DockerError::InvalidMetadata)?;
+
+        let mut descriptors = Vec::new();
+        let mut diff_ids = Vec::new();
+        for layer_path in &layer_paths {
+            let (descriptor, diff_id) = layers
+                .get(layer_path)
+                .ok_or_else(|| DockerError::InvalidArchive(format!("missing {}", layer_path)))?;
+            descriptors.push(descriptor.clone());
+            diff_ids.push(diff_id.clone());
+        }
+        if !image_config.diff_ids().is_empty() && image_config.diff_ids() != &diff_ids[..] {
+            return Err(DockerError::InvalidArchive(format!(
+                "the layers do not match the configuration {}",
+                config_path
+            )));
+        }
+
+        // The configuration is stored as it is, so that its digest stays the image identifier
+        let (digest, size) = image_store
+            .blob_store()
+            .put(&config[..], None)
+            .map_err(DockerError::BlobError)?;
+        let config = Descriptor::new(CONFIG_MEDIA_TYPE, digest, size);
+        for name in names {
+            image_store
+                .create_manifest(name.clone(), config.clone(), descriptors.clone())
+                .map_err(DockerError::ImageError)?;
+            imported_names.push(name);
+        }
+    }
+
+    Ok(imported_names)
+}
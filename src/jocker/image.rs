@@ -1,9 +1,36 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 use failure::Fail;
-use flate2::read::GzDecoder;
-use tar::Archive;
+use flate2::read::GzEncoder;
+use flate2::Compression;
+use serde_derive::{Deserialize, Serialize};
+
+use super::blob::{BlobError, BlobStore, DigestReader};
+use super::layer::{self, LayerError, LayerStore};
+
+/// Media type of image manifests
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Media type of image configurations
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+
+/// Media type of layers, stored as gzip-compressed tarballs
+pub const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+/// Name of the archive of the root filesystem of the images stored by the versions of jocker
+/// predating the layers, which are converted to single-layer images on first use
+const LEGACY_ARCHIVE: &str = "image.tar.gz";
+
+/// Set a `KEY=VALUE` variable in an environment, replacing any previous value for the key
+pub fn set_environment_variable(env: &mut Vec<String>, variable: &str) {
+    let key = variable.splitn(2, '=').next().unwrap_or("");
+
+    env.retain(|existing| existing.splitn(2, '=').next() != Some(key));
+    env.push(variable.to_string());
+}
 
 #[derive(Fail, Debug)]
 pub enum ImageError {
@@ -11,21 +38,340 @@ pub enum ImageError {
     #[fail(display = "invalid image")]
     InvalidImage,
 
-    /// An image could not be unpacked
-    #[fail(display = "unable to unpack image: {}", _0)]
-    UnpackError(std::io::Error),
+    /// The manifest or the configuration of an image could not be parsed
+    #[fail(display = "invalid image metadata: {}", _0)]
+    InvalidMetadata(serde_json::Error),
+
+    /// An image could not be given a name
+    #[fail(display = "invalid image name {:?}", _0)]
+    InvalidName(String),
 
     /// An intermediate directory could not be created in the cache
     #[fail(display = "unable to create directory: {}", _0)]
     CannotCreateDirectory(std::io::Error),
 
-    /// A tarball could not be imported in the cache
-    #[fail(display = "unable to import tarball: {}", _0)]
-    CannotImportTarball(std::io::Error),
+    /// A layer could not be imported
+    #[fail(display = "unable to import layer: {}", _0)]
+    CannotImportLayer(std::io::Error),
+
+    /// The reference to the manifest of an image could not be written
+    #[fail(display = "unable to save image: {}", _0)]
+    CannotSaveImage(std::io::Error),
 
     /// An image could not be removed
     #[fail(display = "unable to remove image: {}", _0)]
     CannotRemoveImage(std::io::Error),
+
+    /// A blob of an image could not be stored or used
+    #[fail(display = "{}", _0)]
+    BlobError(BlobError),
+
+    /// A layer of an image could not be extracted or removed
+    #[fail(display = "{}", _0)]
+    LayerError(LayerError),
+}
+
+/// Reference to a blob, along with the type and the size of its content
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+}
+
+impl Descriptor {
+    /// Create a [`Descriptor`] from the digest and the size of a blob
+    pub fn new(media_type: &str, digest: String, size: u64) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest,
+            size,
+        }
+    }
+
+    /// Retrieve the type of the content of the blob
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// Retrieve the digest of the blob
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    /// Retrieve the size of the blob
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Manifest of an image, referencing its configuration and its layers, from the bottom one to
+/// the top one
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    schema_version: u32,
+    #[serde(default)]
+    media_type: Option<String>,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+impl Manifest {
+    fn new(config: Descriptor, layers: Vec<Descriptor>) -> Self {
+        Self {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+            config,
+            layers,
+        }
+    }
+
+    /// Retrieve the media type of the manifest, if it specifies one
+    pub fn media_type(&self) -> Option<&str> {
+        self.media_type.as_deref()
+    }
+
+    /// Retrieve the reference to the configuration of the image
+    pub fn config(&self) -> &Descriptor {
+        &self.config
+    }
+
+    /// Retrieve the references to the layers of the image, from the bottom one to the top one
+    pub fn layers(&self) -> &[Descriptor] {
+        &self.layers
+    }
+}
+
+/// Description of the layers making up the filesystem of an image
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct RootFs {
+    #[serde(rename = "type")]
+    kind: String,
+    diff_ids: Vec<String>,
+}
+
+/// Default parameters of the processes of the containers created from an image
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExecutionConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exposed_ports: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    env: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entrypoint: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cmd: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volumes: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    labels: Option<BTreeMap<String, String>>,
+}
+
+impl ExecutionConfig {
+    /// Retrieve the `USER[:GROUP]` the processes run as, if not root
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref().filter(|s| !s.is_empty())
+    }
+
+    /// Set the `USER[:GROUP]` the processes run as
+    pub fn set_user(&mut self, user: Option<String>) {
+        self.user = user;
+    }
+
+    /// Obtain an iterator over the ports the processes listen on, such as `80/tcp`
+    pub fn exposed_ports(&self) -> impl Iterator<Item = &str> {
+        self.exposed_ports
+            .iter()
+            .flat_map(BTreeMap::keys)
+            .map(String::as_str)
+    }
+
+    /// Add a port the processes listen on, such as `80/tcp`
+    pub fn add_exposed_port(&mut self, port: String) {
+        self.exposed_ports
+            .get_or_insert_with(BTreeMap::new)
+            .insert(port, serde_json::Value::Object(Default::default()));
+    }
+
+    /// Retrieve the `KEY=VALUE` environment variables of the processes
+    pub fn env(&self) -> &[String] {
+        self.env.as_ref().map_or(&[], Vec::as_slice)
+    }
+
+    /// Set a `KEY=VALUE` environment variable for the processes
+    pub fn set_env(&mut self, variable: &str) {
+        set_environment_variable(self.env.get_or_insert_with(Vec::new), variable);
+    }
+
+    /// Retrieve the program the command of the processes is passed to, followed by its
+    /// arguments
+    pub fn entrypoint(&self) -> &[String] {
+        self.entrypoint.as_ref().map_or(&[], Vec::as_slice)
+    }
+
+    /// Set the program the command of the processes is passed to, followed by its arguments
+    pub fn set_entrypoint(&mut self, entrypoint: Option<Vec<String>>) {
+        self.entrypoint = entrypoint;
+    }
+
+    /// Retrieve the default command of the processes, passed to the entrypoint if there is one
+    pub fn cmd(&self) -> &[String] {
+        self.cmd.as_ref().map_or(&[], Vec::as_slice)
+    }
+
+    /// Set the default command of the processes, passed to the entrypoint if there is one
+    pub fn set_cmd(&mut self, cmd: Option<Vec<String>>) {
+        self.cmd = cmd;
+    }
+
+    /// Obtain an iterator over the paths where the containers store data outside of the image
+    pub fn volumes(&self) -> impl Iterator<Item = &str> {
+        self.volumes
+            .iter()
+            .flat_map(BTreeMap::keys)
+            .map(String::as_str)
+    }
+
+    /// Add a path where the containers store data outside of the image
+    pub fn add_volume(&mut self, path: String) {
+        self.volumes
+            .get_or_insert_with(BTreeMap::new)
+            .insert(path, serde_json::Value::Object(Default::default()));
+    }
+
+    /// Retrieve the working directory of the processes, if not the root
+    pub fn working_dir(&self) -> Option<&str> {
+        self.working_dir.as_deref().filter(|s| !s.is_empty())
+    }
+
+    /// Set the working directory of the processes
+    pub fn set_working_dir(&mut self, working_dir: Option<String>) {
+        self.working_dir = working_dir;
+    }
+
+    /// Obtain an iterator over the labels of the image, as `(key, value)` pairs
+    pub fn labels(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.labels
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Set a label of the image
+    pub fn set_label(&mut self, key: String, value: String) {
+        self.labels
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key, value);
+    }
+}
+
+/// Configuration of an image
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageConfig {
+    architecture: String,
+    os: String,
+    #[serde(default)]
+    config: ExecutionConfig,
+    #[serde(default)]
+    rootfs: RootFs,
+}
+
+impl ImageConfig {
+    /// Create the configuration of an image without layers, running on the host
+    pub fn new() -> Self {
+        let architecture = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            "x86" => "386",
+            arch => arch,
+        };
+
+        Self {
+            architecture: architecture.to_string(),
+            os: std::env::consts::OS.to_string(),
+            config: ExecutionConfig::default(),
+            rootfs: RootFs {
+                kind: "layers".to_string(),
+                diff_ids: Vec::new(),
+            },
+        }
+    }
+
+    /// Retrieve the default parameters of the processes of the containers
+    pub fn execution_config(&self) -> &ExecutionConfig {
+        &self.config
+    }
+
+    /// Retrieve a mutable reference to the default parameters of the processes of the
+    /// containers
+    pub fn execution_config_mut(&mut self) -> &mut ExecutionConfig {
+        &mut self.config
+    }
+
+    /// Retrieve the digests of the uncompressed layers of the image, from the bottom one to the
+    /// top one
+    pub fn diff_ids(&self) -> &[String] {
+        &self.rootfs.diff_ids
+    }
+
+    /// Add the digest of an uncompressed layer on top of the layers of the image
+    pub fn add_diff_id(&mut self, diff_id: String) {
+        self.rootfs.diff_ids.push(diff_id);
+    }
+}
+
+/// Reference to the manifest of an image, stored in its directory
+#[derive(Serialize, Deserialize, Debug)]
+struct ImageReference {
+    manifest: String,
+}
+
+/// Encode the name of an image as the name of its directory, so that names such as
+/// `registry:5000/team/app:1.0` can be used
+fn encode_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '%' => encoded.push_str("%25"),
+            '/' => encoded.push_str("%2F"),
+            '.' if i == 0 => encoded.push_str("%2E"),
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// Decode the name of an image from the name of its directory
+fn decode_name(encoded: &str) -> String {
+    let mut name = String::with_capacity(encoded.len());
+    let mut rest = encoded;
+
+    while let Some(index) = rest.find('%') {
+        name.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let decoded = match rest.get(..3) {
+            Some("%25") => '%',
+            Some("%2F") => '/',
+            Some("%2E") => '.',
+            _ => {
+                name.push('%');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        name.push(decoded);
+        rest = &rest[3..];
+    }
+    name.push_str(rest);
+    name
 }
 
 /// Structure representing a handle over a jocker image stored at a given path
@@ -40,8 +386,10 @@ impl Image {
     }
 
     /// Retrieve the name of the image
-    pub fn name(&self) -> &Path {
-        Path::new(self.path.file_stem().expect("invalid image path"))
+    pub fn name(&self) -> String {
+        let file_name = self.path.file_name().expect("invalid image path");
+
+        decode_name(&file_name.to_string_lossy())
     }
 
     /// Retrieve the path to the image
@@ -49,28 +397,55 @@ impl Image {
         &self.path
     }
 
-    /// Extract the content of the image to the given directory
-    pub fn extract_to<T: AsRef<Path>>(&self, dest_path: T) -> Result<ExtractedImage, ImageError> {
-        let dest_path = dest_path.as_ref();
-        let file = std::fs::File::open(self.path.join("image.tar.gz"))
-            .map_err(|_| ImageError::InvalidImage)?;
-        let mut archive = Archive::new(GzDecoder::new(file));
+    /// Retrieve the digest of the manifest of the image
+    pub fn manifest_digest(&self) -> Result<String, ImageError> {
+        let file =
+            fs::File::open(self.path.join("image.json")).map_err(|_| ImageError::InvalidImage)?;
+        let reference: ImageReference =
+            serde_json::from_reader(&file).map_err(|_| ImageError::InvalidImage)?;
+
+        Ok(reference.manifest)
+    }
+}
+
+/// Structure representing a handle over a jocker image whose layers are extracted, ready to be
+/// mounted as the lower directories of an overlay filesystem
+#[derive(Debug)]
+pub struct ExtractedImage {
+    layer_paths: Vec<PathBuf>,
+}
 
-        archive.unpack(dest_path).map_err(ImageError::UnpackError)?;
-        Ok(ExtractedImage::new(dest_path.to_path_buf()))
+impl ExtractedImage {
+    /// Retrieve the paths to the extracted layers, from the top one to the bottom one
+    pub fn layer_paths(&self) -> &[PathBuf] {
+        &self.layer_paths
     }
 }
 
 /// Structure representing a handle over a directory storing jocker images
+///
+/// Images only reference their manifest, which is stored with their configuration and their
+/// layers in a blob store, so that the blobs shared by several images are only stored once.
 #[derive(Debug)]
 pub struct ImageStore<'a> {
     images_dir: &'a Path,
+    blob_store: BlobStore<'a>,
+    layer_store: LayerStore<'a>,
 }
 
 impl<'a> ImageStore<'a> {
-    /// Create an [`ImageStore`] from a path
-    pub fn from_directory(images_dir: &'a Path) -> Self {
-        Self { images_dir }
+    /// Create an [`ImageStore`] from a path, storing the blobs of its images in a blob store
+    /// and extracting their layers in a layer store
+    pub fn from_directory(
+        images_dir: &'a Path,
+        blob_store: BlobStore<'a>,
+        layer_store: LayerStore<'a>,
+    ) -> Self {
+        Self {
+            images_dir,
+            blob_store,
+            layer_store,
+        }
     }
 
     /// Retrieve the path to the root directory for this store
@@ -78,94 +453,290 @@ impl<'a> ImageStore<'a> {
         &self.images_dir
     }
 
+    /// Retrieve the blob store holding the blobs of the images
+    pub fn blob_store(&self) -> &BlobStore<'a> {
+        &self.blob_store
+    }
+
+    /// Convert an image stored as a single archive by an older version of jocker into a
+    /// single-layer image, removing the copy of its root filesystem extracted for the containers
+    fn migrate_image(&self, image: &Image) -> Result<(), ImageError> {
+        let archive_path = image.path().join(LEGACY_ARCHIVE);
+        if image.path().join("image.json").exists() || !archive_path.exists() {
+            return Ok(());
+        }
+
+        self.add_layer(image.name(), &archive_path, None)?;
+        fs::remove_file(&archive_path).map_err(ImageError::CannotRemoveImage)?;
+        if let Some(base_dir) = self.images_dir.parent() {
+            let _ = fs::remove_dir_all(base_dir.join("extracted_images").join(image.name()));
+        }
+        Ok(())
+    }
+
+    /// Get a handle over the image stored in a directory, converting it first if it was stored
+    /// by an older version of jocker
+    fn open_image(&self, path: PathBuf) -> Image {
+        let image = Image::new(path);
+
+        if let Err(e) = self.migrate_image(&image) {
+            eprintln!("warning: cannot convert the image {}: {}", image.name(), e);
+        }
+        image
+    }
+
     /// Obtain an iterator over the images available in this store
     pub fn images(
         &self,
-    ) -> Result<impl Iterator<Item = Result<Image, std::io::Error>>, std::io::Error> {
+    ) -> Result<impl Iterator<Item = Result<Image, std::io::Error>> + '_, std::io::Error> {
         let entries = std::fs::read_dir(self.images_dir)?;
 
-        Ok(entries.map(|e| e.map(|entry| Image::new(entry.path()))))
+        Ok(entries.map(move |e| e.map(|entry| self.open_image(entry.path()))))
     }
 
     /// Get a handle over a specific image in this store
     pub fn get_image(&self, image_name: &str) -> Option<Image> {
-        let path = self.images_dir.join(image_name);
+        let path = self.images_dir.join(encode_name(image_name));
 
-        if path.exists() {
-            Some(Image::new(path))
+        if !image_name.is_empty() && path.exists() {
+            Some(self.open_image(path))
         } else {
             None
         }
     }
 
-    /// Import an image from a tarball
-    pub fn import_image(&self, name: String, path: &Path) -> Result<Image, ImageError> {
-        let image_path = self.images_dir.join(&name);
+    /// Create or replace an image referencing the manifest with a given digest
+    pub fn save_image(&self, name: String, manifest_digest: String) -> Result<Image, ImageError> {
+        if name.is_empty() {
+            return Err(ImageError::InvalidName(name));
+        }
+        let image_path = self.images_dir.join(encode_name(&name));
         fs::create_dir_all(&image_path).map_err(ImageError::CannotCreateDirectory)?;
-        fs::copy(path, image_path.join("image.tar.gz")).map_err(ImageError::CannotImportTarball)?;
+
+        let reference_path = image_path.join("image.json");
+        let tmp_path = image_path.join("image.json.tmp");
+        let save_result: Result<(), std::io::Error> = try {
+            let file = fs::File::create(&tmp_path)?;
+            let reference = ImageReference {
+                manifest: manifest_digest,
+            };
+            serde_json::to_writer(&file, &reference)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, &reference_path)?;
+        };
+        save_result.map_err(ImageError::CannotSaveImage)?;
 
         Ok(Image::new(image_path))
     }
 
-    /// Duplicate an image
-    pub fn copy_image(&self, name: String, image: &Image) -> Result<Image, ImageError> {
-        let image_archive_path = image.path().join("image.tar.gz");
+    /// Retrieve the manifest of an image, after checking its digest
+    pub fn manifest(&self, image: &Image) -> Result<Manifest, ImageError> {
+        self.manifest_from_digest(&image.manifest_digest()?)
+    }
+
+    /// Retrieve a manifest from its digest, after checking it
+    pub fn manifest_from_digest(&self, digest: &str) -> Result<Manifest, ImageError> {
+        let content = self
+            .blob_store
+            .read(digest)
+            .map_err(ImageError::BlobError)?;
 
-        self.import_image(name, &image_archive_path)
+        serde_json::from_slice(&content).map_err(ImageError::InvalidMetadata)
     }
 
-    /// Remove an image from the store
-    pub fn remove_image(&self, image: Image) -> Result<(), ImageError> {
-        fs::remove_dir_all(image.path()).map_err(ImageError::CannotRemoveImage)
+    /// Retrieve the configuration of an image from its manifest, after checking its digest
+    pub fn config(&self, manifest: &Manifest) -> Result<ImageConfig, ImageError> {
+        let content = self
+            .blob_store
+            .read(manifest.config().digest())
+            .map_err(ImageError::BlobError)?;
+
+        serde_json::from_slice(&content).map_err(ImageError::InvalidMetadata)
     }
-}
 
-/// Structure representing a handle over a jocker image extracted at a given path
-#[derive(Debug)]
-pub struct ExtractedImage {
-    path: PathBuf,
-}
+    /// Store a value as a JSON blob
+    fn put_json<T: serde::Serialize>(
+        &self,
+        media_type: &str,
+        value: &T,
+    ) -> Result<Descriptor, ImageError> {
+        let content = serde_json::to_vec(value).map_err(ImageError::InvalidMetadata)?;
+        let (digest, size) = self
+            .blob_store
+            .put(&content[..], None)
+            .map_err(ImageError::BlobError)?;
+
+        Ok(Descriptor::new(media_type, digest, size))
+    }
 
-impl ExtractedImage {
-    fn new(path: PathBuf) -> Self {
-        Self { path }
+    /// Create or replace an image from its configuration and its layers, whose blobs must
+    /// already be stored
+    pub fn create_image(
+        &self,
+        name: String,
+        config: &ImageConfig,
+        layers: Vec<Descriptor>,
+    ) -> Result<Image, ImageError> {
+        let config = self.put_json(CONFIG_MEDIA_TYPE, config)?;
+
+        self.create_manifest(name, config, layers)
     }
 
-    /// Retrieve the name of the image
-    pub fn name(&self) -> &Path {
-        Path::new(self.path.file_stem().expect("invalid image path"))
+    /// Create or replace an image from the references to its configuration and its layers,
+    /// whose blobs must already be stored
+    pub fn create_manifest(
+        &self,
+        name: String,
+        config: Descriptor,
+        layers: Vec<Descriptor>,
+    ) -> Result<Image, ImageError> {
+        let manifest = self.put_json(MANIFEST_MEDIA_TYPE, &Manifest::new(config, layers))?;
+
+        self.save_image(name, manifest.digest().to_string())
     }
 
-    /// Retrieve the path to the image
-    pub fn path(&self) -> &Path {
-        &self.path
+    /// Store an uncompressed layer archive read from a reader as a gzip-compressed blob, and
+    /// return its reference along with the digest of its uncompressed content
+    pub fn put_layer<R: Read>(&self, reader: R) -> Result<(Descriptor, String), ImageError> {
+        let mut diff_reader = DigestReader::new(reader);
+        let (digest, size) = self
+            .blob_store
+            .put(
+                GzEncoder::new(&mut diff_reader, Compression::default()),
+                None,
+            )
+            .map_err(ImageError::BlobError)?;
+        let (diff_id, _) = diff_reader
+            .finish()
+            .map_err(ImageError::CannotImportLayer)?;
+
+        Ok((Descriptor::new(LAYER_MEDIA_TYPE, digest, size), diff_id))
     }
-}
 
-/// Structure representing a handle over a directory storing extracted jocker images
-pub struct ExtractedImageStore<'a> {
-    images_dir: &'a Path,
-}
+    /// Create an image from a tarball, applied as a new layer on top of the image with a given
+    /// manifest if one is given
+    pub fn add_layer(
+        &self,
+        name: String,
+        archive_path: &Path,
+        parent: Option<Manifest>,
+    ) -> Result<Image, ImageError> {
+        let (mut config, mut layers) = match parent {
+            Some(manifest) => (self.config(&manifest)?, manifest.layers),
+            None => (ImageConfig::new(), Vec::new()),
+        };
+
+        let diff_id = layer::diff_id(archive_path).map_err(ImageError::CannotImportLayer)?;
+        let (digest, size) = self
+            .blob_store
+            .put_file(archive_path, None)
+            .map_err(ImageError::BlobError)?;
+        config.add_diff_id(diff_id);
+        layers.push(Descriptor::new(LAYER_MEDIA_TYPE, digest, size));
+
+        self.create_image(name, &config, layers)
+    }
 
-impl<'a> ExtractedImageStore<'a> {
-    /// Create an [`ExtractedImageStore`] from a path
-    pub fn from_directory(images_dir: &'a Path) -> Self {
-        Self { images_dir }
+    /// Import an image from a tarball
+    pub fn import_image(&self, name: String, path: &Path) -> Result<Image, ImageError> {
+        self.add_layer(name, path, None)
     }
 
-    /// Retrieve the path to the root directory for this store
-    pub fn path(&self) -> &Path {
-        &self.images_dir
+    /// Duplicate an image, which shares the manifest of the original
+    pub fn copy_image(&self, name: String, image: &Image) -> Result<Image, ImageError> {
+        self.save_image(name, image.manifest_digest()?)
     }
 
-    /// Get a handle over a specific image in this store
-    pub fn get_extracted_image(&self, image_name: &str) -> Option<ExtractedImage> {
-        let path = self.images_dir.join(image_name);
+    /// Extract the layers of the image with a given manifest which are not extracted yet
+    pub fn extract_image(&self, manifest: &Manifest) -> Result<ExtractedImage, ImageError> {
+        let layer_paths = manifest
+            .layers()
+            .iter()
+            .rev()
+            .map(|layer| self.layer_store.extract(&self.blob_store, layer.digest()))
+            .collect::<Result<_, _>>()
+            .map_err(ImageError::LayerError)?;
+
+        Ok(ExtractedImage { layer_paths })
+    }
 
-        if path.exists() {
-            Some(ExtractedImage::new(path))
-        } else {
-            None
+    /// Collect the digests of the blobs of the image with a given manifest: the manifest, the
+    /// configuration and the layers
+    fn image_digests(&self, manifest_digest: &str) -> Result<Vec<String>, ImageError> {
+        let manifest = self.manifest_from_digest(manifest_digest)?;
+
+        let mut digests = vec![
+            manifest_digest.to_string(),
+            manifest.config().digest().to_string(),
+        ];
+        digests.extend(
+            manifest
+                .layers()
+                .iter()
+                .map(|layer| layer.digest().to_string()),
+        );
+        Ok(digests)
+    }
+
+    /// Collect the digests of the blobs used by the images of this store but a given one, and
+    /// by the images with the other given manifests
+    fn used_digests(
+        &self,
+        excluded_image: &Image,
+        used_manifests: &[String],
+    ) -> Result<HashSet<String>, ImageError> {
+        let mut manifest_digests = used_manifests.to_vec();
+        for image in self.images().map_err(ImageError::CannotRemoveImage)? {
+            let image = image.map_err(ImageError::CannotRemoveImage)?;
+            if image.path() != excluded_image.path() {
+                manifest_digests.push(image.manifest_digest()?);
+            }
+        }
+
+        let mut digests = HashSet::new();
+        for manifest_digest in manifest_digests {
+            digests.extend(self.image_digests(&manifest_digest)?);
+        }
+        Ok(digests)
+    }
+
+    /// Remove an image from the store, along with its blobs and layers used neither by another
+    /// image nor by the images with the given manifests, such as those of the containers
+    ///
+    /// The blobs of a broken image cannot be found, so only its directory is removed.
+    pub fn remove_image(&self, image: Image, used_manifests: &[String]) -> Result<(), ImageError> {
+        let image_digests = match image.manifest_digest() {
+            Ok(manifest_digest) => self.image_digests(&manifest_digest).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+        // The blobs in use are collected first, so that nothing is removed if they cannot be
+        let used_digests = self.used_digests(&image, used_manifests)?;
+        let unused_digests: HashSet<_> = image_digests
+            .into_iter()
+            .filter(|digest| !used_digests.contains(digest))
+            .collect();
+
+        fs::remove_dir_all(image.path()).map_err(ImageError::CannotRemoveImage)?;
+
+        let layer_digests = self
+            .layer_store
+            .digests()
+            .map_err(ImageError::CannotRemoveImage)?;
+        for digest in layer_digests.filter(|digest| unused_digests.contains(digest)) {
+            self.layer_store
+                .remove_layer(&self.blob_store, &digest)
+                .map_err(ImageError::LayerError)?;
+        }
+
+        let blob_digests = self
+            .blob_store
+            .digests()
+            .map_err(ImageError::CannotRemoveImage)?;
+        for digest in blob_digests.filter(|digest| unused_digests.contains(digest)) {
+            self.blob_store
+                .remove_blob(&digest)
+                .map_err(ImageError::BlobError)?;
         }
+        Ok(())
     }
 }
@@ -0,0 +1,137 @@
+use std::ffi::CString;
+
+use failure::Fail;
+use nix::sys::signal::{kill, signal, sigprocmask, SigHandler, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{
+    execv, fork, getpid, isatty, setgid, setgroups, setpgid, setuid, tcsetpgrp, ForkResult, Gid,
+    Pid, Uid,
+};
+
+/// Error type for errors of the init process
+#[derive(Fail, Debug)]
+pub enum InitError {
+    /// The command to run is invalid
+    #[fail(display = "invalid command")]
+    InvalidCommand,
+
+    /// The signals could not be configured
+    #[fail(display = "cannot setup the signals: {}", _0)]
+    CannotSetupSignals(nix::Error),
+
+    /// The process running the command could not be created
+    #[fail(display = "cannot fork the command: {}", _0)]
+    CannotFork(nix::Error),
+
+    /// The processes could not be waited for
+    #[fail(display = "cannot wait for the command: {}", _0)]
+    CannotWait(nix::Error),
+}
+
+/// Credentials the command is run with, while the init process keeps its own
+pub struct Credentials {
+    uid: Uid,
+    gid: Gid,
+    groups: Option<Vec<Gid>>,
+}
+
+impl Credentials {
+    /// Create credentials, leaving the supplementary groups unchanged if there are none
+    pub fn new(uid: Uid, gid: Gid, groups: Option<Vec<Gid>>) -> Credentials {
+        Credentials { uid, gid, groups }
+    }
+
+    /// Switch the current process to the credentials
+    fn apply(&self) -> nix::Result<()> {
+        if let Some(groups) = &self.groups {
+            setgroups(groups)?;
+        }
+        setgid(self.gid)?;
+        setuid(self.uid)
+    }
+}
+
+/// Replace the current process, forked by the init process, with the command
+fn exec_child(program: &CString, args: &[CString], credentials: &Credentials) -> ! {
+    // Give the command its own process group, in the foreground of the terminal if any, so
+    // that keyboard signals are sent to it rather than forwarded by the init process
+    let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+    if isatty(0).unwrap_or(false) {
+        let _ = unsafe { signal(Signal::SIGTTOU, SigHandler::SigIgn) };
+        let _ = tcsetpgrp(0, getpid());
+        let _ = unsafe { signal(Signal::SIGTTOU, SigHandler::SigDfl) };
+    }
+    let _ = sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None);
+
+    if let Err(e) = credentials.apply() {
+        eprintln!("error: cannot switch to the user of the command: {}", e);
+        std::process::exit(126);
+    }
+
+    let error = execv(program, args).unwrap_err();
+    eprintln!(
+        "error: cannot execute {}: {}",
+        program.to_string_lossy(),
+        error
+    );
+    std::process::exit(127);
+}
+
+/// Reap every child which has exited, returning the exit code of the command if it has exited
+fn reap_children(command_pid: Pid) -> Result<Option<i32>, InitError> {
+    let mut exit_code = None;
+
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) if pid == command_pid => exit_code = Some(code),
+            Ok(WaitStatus::Signaled(pid, signal, _)) if pid == command_pid => {
+                exit_code = Some(128 + signal as i32)
+            }
+            Ok(WaitStatus::StillAlive) | Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => {
+                return Ok(exit_code)
+            }
+            Ok(_) => {}
+            Err(e) => return Err(InitError::CannotWait(e)),
+        }
+    }
+}
+
+/// Run a command as the child of the current process, acting as the init process of its
+/// container until the command exits, and return its exit code
+///
+/// The command is given as the path to the program followed by its arguments, starting with
+/// its name. The signals received are forwarded to the command, and the orphaned processes
+/// reparented to the init process are reaped. Only the command is run with the credentials.
+pub fn run(command: &[String], credentials: &Credentials) -> Result<i32, InitError> {
+    let c_command = command
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| InitError::InvalidCommand)?;
+    if c_command.len() < 2 {
+        return Err(InitError::InvalidCommand);
+    }
+
+    // Signals are only handled synchronously, blocking them until they are waited for
+    let signals = SigSet::all();
+    sigprocmask(SigmaskHow::SIG_SETMASK, Some(&signals), None)
+        .map_err(InitError::CannotSetupSignals)?;
+
+    let command_pid = match fork().map_err(InitError::CannotFork)? {
+        ForkResult::Parent { child } => child,
+        ForkResult::Child => exec_child(&c_command[0], &c_command[1..], credentials),
+    };
+
+    loop {
+        match signals.wait().map_err(InitError::CannotSetupSignals)? {
+            Signal::SIGCHLD => {
+                if let Some(exit_code) = reap_children(command_pid)? {
+                    return Ok(exit_code);
+                }
+            }
+            signal => {
+                let _ = kill(command_pid, signal);
+            }
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+
+use failure::Fail;
+use flate2::read::GzDecoder;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use tar::{Archive, EntryType, Header};
+
+use super::blob::{digest_hex, BlobError, BlobStore, DigestReader, SHA256_PREFIX};
+use super::userns;
+
+/// Prefix of the archive entries marking files removed from the lower layers
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Name of the archive entry marking a directory which hides the content of the lower layers
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Extended attribute marking a directory of an overlay filesystem as opaque
+const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+/// Extended attribute marking a directory as opaque for the overlay filesystems mounted with
+/// the `userxattr` option, since unprivileged users cannot set `trusted.*` attributes
+const USER_OPAQUE_XATTR: &str = "user.overlay.opaque";
+
+/// Error type for layer-related errors
+#[derive(Fail, Debug)]
+pub enum LayerError {
+    /// The archive of a layer could not be found or verified
+    #[fail(display = "invalid layer: {}", _0)]
+    InvalidLayer(BlobError),
+
+    /// A layer could not be unpacked
+    #[fail(display = "unable to unpack layer: {}", _0)]
+    UnpackError(std::io::Error),
+
+    /// An extracted layer could not be removed
+    #[fail(display = "unable to remove layer: {}", _0)]
+    CannotRemoveLayer(std::io::Error),
+}
+
+/// Wrap a reader to decompress the data read through it if it is gzip-compressed
+pub fn decompress<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>, std::io::Error> {
+    let mut reader = BufReader::new(reader);
+    let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+
+    if is_gzip {
+        Ok(Box::new(GzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Compute the digest of the uncompressed content of a layer archive, which identifies the
+/// changes it makes independently of their compression
+pub fn diff_id(archive_path: &Path) -> Result<String, std::io::Error> {
+    let file = fs::File::open(archive_path)?;
+    let (digest, _) = DigestReader::new(GzDecoder::new(file)).finish()?;
+
+    Ok(digest)
+}
+
+/// Structure representing a handle over a directory storing the extracted layers of images,
+/// identified by the digest of their archive in a blob store
+#[derive(Debug)]
+pub struct LayerStore<'a> {
+    layers_dir: &'a Path,
+}
+
+impl<'a> LayerStore<'a> {
+    /// Create a [`LayerStore`] from a path
+    pub fn from_directory(layers_dir: &'a Path) -> Self {
+        Self { layers_dir }
+    }
+
+    /// Retrieve the path to the root directory for this store
+    pub fn path(&self) -> &Path {
+        &self.layers_dir
+    }
+
+    /// Retrieve the path to the directory holding a layer extracted from a given archive
+    fn layer_path(&self, blob_store: &BlobStore, digest: &str) -> Result<PathBuf, LayerError> {
+        let blob_path = blob_store
+            .blob_path(digest)
+            .map_err(LayerError::InvalidLayer)?;
+        let hex = blob_path.file_name().expect("invalid blob path");
+
+        Ok(self.layers_dir.join(hex))
+    }
+
+    /// Obtain an iterator over the digests of the archives of the layers extracted in this store
+    pub fn digests(&self) -> Result<impl Iterator<Item = String>, std::io::Error> {
+        let entries = match fs::read_dir(self.layers_dir) {
+            Ok(entries) => Some(entries),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(entries
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .map(|hex| format!("{}{}", SHA256_PREFIX, hex))
+            .filter(|digest| digest_hex(digest).is_ok()))
+    }
+
+    /// Extract the layer archive with a given digest into a directory usable as a lower
+    /// directory of an overlay filesystem, unless it already is, and return the path to this
+    /// directory
+    ///
+    /// The archive is checked against its digest while it is extracted.
+    pub fn extract(&self, blob_store: &BlobStore, digest: &str) -> Result<PathBuf, LayerError> {
+        let layer_path = self.layer_path(blob_store, digest)?;
+        let diff_path = layer_path.join("diff");
+        if diff_path.exists() {
+            return Ok(diff_path);
+        }
+
+        // The layer is unpacked aside, so that an interrupted or corrupted extraction is never
+        // used
+        let tmp_path = layer_path.join("diff.tmp");
+        if tmp_path.exists() {
+            fs::remove_dir_all(&tmp_path).map_err(LayerError::UnpackError)?;
+        }
+        let mut reader = blob_store.open(digest).map_err(LayerError::InvalidLayer)?;
+        let unpack_result = unpack_diff(&mut reader, &tmp_path);
+        // A corrupted archive usually fails to unpack, which is better reported as corrupted
+        let extract_result = reader
+            .verify(digest)
+            .map_err(LayerError::InvalidLayer)
+            .and_then(|_| unpack_result.map_err(LayerError::UnpackError))
+            .and_then(|_| fs::rename(&tmp_path, &diff_path).map_err(LayerError::UnpackError));
+        if extract_result.is_err() {
+            let _ = fs::remove_dir_all(&tmp_path);
+        }
+        extract_result?;
+
+        Ok(diff_path)
+    }
+
+    /// Remove the layer extracted from the archive with a given digest
+    pub fn remove_layer(&self, blob_store: &BlobStore, digest: &str) -> Result<(), LayerError> {
+        fs::remove_dir_all(self.layer_path(blob_store, digest)?)
+            .map_err(LayerError::CannotRemoveLayer)
+    }
+}
+
+/// Convert a path to a C string, for the system calls not wrapped by the standard library
+fn c_path(path: &Path) -> Result<CString, std::io::Error> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Get the extended attribute marking opaque directories in the overlay filesystems of the
+/// current user
+fn opaque_xattr() -> &'static str {
+    if userns::is_rootless() {
+        USER_OPAQUE_XATTR
+    } else {
+        OPAQUE_XATTR
+    }
+}
+
+/// Check whether a directory of an overlay filesystem is marked as opaque
+fn is_opaque(path: &Path) -> Result<bool, std::io::Error> {
+    let path = c_path(path)?;
+    let name = CString::new(opaque_xattr())?;
+    let mut value = [0u8; 1];
+
+    let size = unsafe {
+        libc::lgetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_mut_ptr() as *mut libc::c_void,
+            value.len(),
+        )
+    };
+    Ok(size == 1 && value[0] == b'y')
+}
+
+/// Mark a directory of an overlay filesystem as opaque
+fn set_opaque(path: &Path) -> Result<(), std::io::Error> {
+    let path = c_path(path)?;
+    let name = CString::new(opaque_xattr())?;
+    let value = b"y";
+
+    let result = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Append an empty file marking a whiteout to an archive
+fn append_whiteout<T: Write>(tar: &mut tar::Builder<T>, path: &Path) -> Result<(), std::io::Error> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(0);
+    header.set_mode(0o644);
+
+    tar.append_data(&mut header, path, std::io::empty())
+}
+
+/// Append the content of the upper directory of an overlay filesystem to an archive, ignoring
+/// special files
+///
+/// Whiteout devices, marking removed files, become `.wh.<name>` entries, and opaque directories
+/// are followed by a `.wh..wh..opq` entry.
+pub fn append_diff<T: Write>(
+    tar: &mut tar::Builder<T>,
+    diff_path: &Path,
+) -> Result<(), std::io::Error> {
+    let mut stack = vec![(diff_path.to_path_buf(), true)];
+
+    while let Some((src, is_dir)) = stack.pop() {
+        let dest = src.strip_prefix(diff_path).unwrap().to_path_buf();
+        if !is_dir {
+            tar.append_path_with_name(&src, &dest)?;
+            continue;
+        }
+
+        if dest != Path::new("") {
+            tar.append_dir(&dest, &src)?;
+            if is_opaque(&src)? {
+                append_whiteout(tar, &dest.join(OPAQUE_WHITEOUT))?;
+            }
+        }
+        for entry in fs::read_dir(&src)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_char_device() && entry.metadata()?.rdev() == 0 {
+                let mut name = OsString::from(WHITEOUT_PREFIX);
+                name.push(entry.file_name());
+                append_whiteout(tar, &dest.join(name))?;
+            } else if file_type.is_file() || file_type.is_dir() || file_type.is_symlink() {
+                stack.push((entry.path(), file_type.is_dir()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create the directories leading to a path relative to a destination directory, returning the
+/// full path to the last one
+///
+/// Symbolic links unpacked by earlier entries are not followed, and fail the creation, so that
+/// the entries cannot reach outside of the destination directory.
+fn create_parent_directories(dest_path: &Path, path: &Path) -> Result<PathBuf, std::io::Error> {
+    let mut parent_path = dest_path.to_path_buf();
+
+    for component in path.components() {
+        let name = match component {
+            Component::Normal(name) => name,
+            Component::CurDir => continue,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid path {}", path.display()),
+                ))
+            }
+        };
+        parent_path.push(name);
+
+        match fs::symlink_metadata(&parent_path) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is not a directory",
+                        parent_path.strip_prefix(dest_path).unwrap().display()
+                    ),
+                ))
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => fs::create_dir(&parent_path)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(parent_path)
+}
+
+/// Unpack a layer archive to a directory, turning its whiteout entries back into the whiteout
+/// devices and opaque directories of overlay filesystems
+fn unpack_diff<R: Read>(reader: R, dest_path: &Path) -> Result<(), std::io::Error> {
+    let mut archive = Archive::new(GzDecoder::new(reader));
+    fs::create_dir_all(dest_path)?;
+
+    // Directories are unpacked last, so that their permissions cannot prevent the unpacking of
+    // their content
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let is_safe = path.components().all(|component| match component {
+            Component::Normal(_) | Component::CurDir => true,
+            _ => false,
+        });
+        let whiteout = path.file_name().and_then(OsStr::to_str).filter(|name| {
+            is_safe && name.starts_with(WHITEOUT_PREFIX) && name.len() > WHITEOUT_PREFIX.len()
+        });
+
+        match whiteout {
+            Some(name) => {
+                let parent_path = create_parent_directories(
+                    dest_path,
+                    path.parent().unwrap_or_else(|| Path::new("")),
+                )?;
+
+                if name == OPAQUE_WHITEOUT {
+                    set_opaque(&parent_path)?;
+                } else {
+                    let whiteout_path = parent_path.join(&name[WHITEOUT_PREFIX.len()..]);
+                    // Unprivileged users can only create whiteout devices since Linux 5.8
+                    mknod(&whiteout_path, SFlag::S_IFCHR, Mode::empty(), makedev(0, 0)).map_err(
+                        |e| match e {
+                            nix::Error::Sys(Errno::EPERM) => std::io::Error::new(
+                                std::io::ErrorKind::PermissionDenied,
+                                format!(
+                                    "cannot create the whiteout of {}: unprivileged users need \
+                                     Linux 5.8 or later to unpack layers removing files",
+                                    whiteout_path.strip_prefix(dest_path).unwrap().display()
+                                ),
+                            ),
+                            _ => std::io::Error::last_os_error(),
+                        },
+                    )?;
+                }
+            }
+            None if entry.header().entry_type() == EntryType::Directory => directories.push(entry),
+            None => {
+                entry.unpack_in(dest_path)?;
+            }
+        }
+    }
+    for mut directory in directories {
+        directory.unpack_in(dest_path)?;
+    }
+
+    Ok(())
+}
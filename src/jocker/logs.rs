@@ -0,0 +1,527 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::Fail;
+use serde_derive::{Deserialize, Serialize};
+
+/// Maximum length of a log entry, longer lines being split in several entries
+const MAX_LINE_LENGTH: usize = 16 * 1024;
+
+/// Delay between two checks for new entries when following a log
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Error type for log-related errors
+#[derive(Fail, Debug)]
+pub enum LogError {
+    /// A log file could not be opened
+    #[fail(display = "cannot open log file {}: {}", _0, _1)]
+    CannotOpenLog(String, std::io::Error),
+
+    /// A log entry could not be written
+    #[fail(display = "cannot write log entry: {}", _0)]
+    CannotWriteLog(std::io::Error),
+
+    /// A log file could not be read
+    #[fail(display = "cannot read log file: {}", _0)]
+    CannotReadLog(std::io::Error),
+
+    /// A time could not be parsed
+    #[fail(display = "invalid time {}", _0)]
+    InvalidTime(String),
+}
+
+/// Convert a number of days since the epoch to a date, as a year, a month and a day
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400;
+
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Convert a date to a number of days since the epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let shifted_month = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Format a time as a RFC 3339 timestamp in UTC, with nanoseconds
+pub fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_nanos()
+    )
+}
+
+/// Parse a RFC 3339 timestamp in UTC, such as `2019-08-20T10:30:00Z`, with optional fractional
+/// seconds
+pub fn parse_time(s: &str) -> Option<SystemTime> {
+    let mut date_time = s.strip_suffix('Z')?.splitn(2, 'T');
+    let date: Vec<u32> = date_time
+        .next()?
+        .split('-')
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    let mut time_fraction = date_time.next()?.splitn(2, '.');
+    let time: Vec<u64> = time_fraction
+        .next()?
+        .split(':')
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    let nanos = match time_fraction.next() {
+        Some(fraction) if fraction.len() <= 9 && fraction.chars().all(|c| c.is_ascii_digit()) => {
+            format!("{:0<9}", fraction).parse().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    match (date.as_slice(), time.as_slice()) {
+        ([year, month, day], [hours, minutes, seconds])
+            if *month >= 1 && *month <= 12 && *day >= 1 && *day <= 31 =>
+        {
+            let days = days_from_civil(i64::from(*year), *month, *day);
+            if days < 0 {
+                return None;
+            }
+            let seconds = days as u64 * 86400 + hours * 3600 + minutes * 60 + seconds;
+            Some(UNIX_EPOCH + Duration::new(seconds, nanos))
+        }
+        _ => None,
+    }
+}
+
+/// Parse the lower bound of a time range, given as a RFC 3339 timestamp, as a Unix timestamp,
+/// or as a duration before the current time such as `30s`, `10m` or `2h`
+pub fn parse_since(s: &str) -> Result<SystemTime, LogError> {
+    let invalid_time = || LogError::InvalidTime(s.to_string());
+
+    let unit = match s.chars().last() {
+        Some('s') => Some(1),
+        Some('m') => Some(60),
+        Some('h') => Some(3600),
+        _ => None,
+    };
+    if let Some(unit) = unit {
+        let amount: u64 = s[..s.len() - 1].parse().map_err(|_| invalid_time())?;
+        return SystemTime::now()
+            .checked_sub(Duration::from_secs(amount * unit))
+            .ok_or_else(invalid_time);
+    }
+
+    if let Ok(timestamp) = s.parse::<f64>() {
+        if timestamp.is_finite() && timestamp >= 0. {
+            return Ok(UNIX_EPOCH + Duration::from_secs_f64(timestamp));
+        }
+    }
+
+    parse_time(s).ok_or_else(invalid_time)
+}
+
+/// Enumeration for the output streams of a container
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// Structure describing a chunk of output of a container, usually a line
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    log: String,
+    stream: LogStream,
+    time: String,
+}
+
+impl LogEntry {
+    /// Retrieve the logged output, including its final newline if it was a complete line
+    pub fn log(&self) -> &str {
+        &self.log
+    }
+
+    /// Retrieve the stream the output was written to
+    pub fn stream(&self) -> LogStream {
+        self.stream
+    }
+
+    /// Retrieve the time the output was written at, as a RFC 3339 timestamp
+    pub fn time(&self) -> &str {
+        &self.time
+    }
+
+    /// Check whether the output was written after a given time
+    pub fn is_after(&self, time: SystemTime) -> bool {
+        parse_time(&self.time).map_or(true, |entry_time| entry_time >= time)
+    }
+}
+
+/// Structure describing how the log of a container is rotated
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogConfig {
+    /// Size of the log file above which it is rotated, in bytes
+    max_size: u64,
+    /// Maximum number of log files, including the current one
+    max_files: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 10 << 20,
+            max_files: 5,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Retrieve the size of the log file above which it is rotated
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Set the size of the log file above which it is rotated
+    pub fn set_max_size(&mut self, max_size: u64) {
+        self.max_size = max_size;
+    }
+
+    /// Retrieve the maximum number of log files
+    pub fn max_files(&self) -> u32 {
+        self.max_files
+    }
+
+    /// Set the maximum number of log files, which has to be at least one
+    pub fn set_max_files(&mut self, max_files: u32) {
+        self.max_files = max_files.max(1);
+    }
+}
+
+/// Retrieve the path to a rotated log file, where the current log file has the index 0
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    if index == 0 {
+        path.to_path_buf()
+    } else {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{}", index));
+        path.with_file_name(file_name)
+    }
+}
+
+/// Structure representing a handle over the log of a container, opened for writing
+pub struct LogWriter {
+    path: PathBuf,
+    config: LogConfig,
+    file: File,
+    size: u64,
+}
+
+impl LogWriter {
+    fn open_file(path: &Path) -> Result<File, LogError> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| LogError::CannotOpenLog(path.display().to_string(), e))
+    }
+
+    /// Open the log at a given path, appending to it
+    pub fn open(path: PathBuf, config: LogConfig) -> Result<Self, LogError> {
+        let file = Self::open_file(&path)?;
+        let size = file.metadata().map_err(LogError::CannotWriteLog)?.len();
+
+        Ok(Self {
+            path,
+            config,
+            file,
+            size,
+        })
+    }
+
+    /// Shift the rotated log files, dropping the oldest one, and start a new log file
+    fn rotate(&mut self) -> Result<(), LogError> {
+        let max_files = self.config.max_files();
+
+        if max_files > 1 {
+            for index in (0..max_files - 1).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, index + 1))
+                        .map_err(LogError::CannotWriteLog)?;
+                }
+            }
+        } else {
+            fs::remove_file(&self.path).map_err(LogError::CannotWriteLog)?;
+        }
+
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Append an entry to the log, rotating it first if it would grow past its maximum size
+    pub fn write_entry(&mut self, stream: LogStream, log: &str) -> Result<(), LogError> {
+        let entry = LogEntry {
+            log: log.to_string(),
+            stream,
+            time: format_time(SystemTime::now()),
+        };
+        let mut line = serde_json::to_vec(&entry)
+            .map_err(|e| LogError::CannotWriteLog(std::io::Error::from(e)))?;
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.config.max_size() {
+            self.rotate()?;
+        }
+        self.file
+            .write_all(&line)
+            .map_err(LogError::CannotWriteLog)?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+}
+
+/// Structure splitting output read in arbitrary chunks into log entries, one per line
+pub struct LineLogger {
+    writer: Arc<Mutex<LogWriter>>,
+    stream: LogStream,
+    pending: Vec<u8>,
+}
+
+impl LineLogger {
+    /// Create a [`LineLogger`] logging the output of a given stream
+    pub fn new(writer: Arc<Mutex<LogWriter>>, stream: LogStream) -> Self {
+        Self {
+            writer,
+            stream,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_entry(&self, log: &[u8]) -> Result<(), LogError> {
+        self.writer
+            .lock()
+            .map_err(|_| LogError::CannotWriteLog(std::io::ErrorKind::Other.into()))?
+            .write_entry(self.stream, &String::from_utf8_lossy(log))
+    }
+
+    /// Log the complete lines of a chunk of output, keeping the rest until it is completed
+    pub fn write(&mut self, data: &[u8]) -> Result<(), LogError> {
+        self.pending.extend_from_slice(data);
+
+        while let Some(end) = self
+            .pending
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map(|position| position + 1)
+            .or_else(|| Some(MAX_LINE_LENGTH).filter(|max| self.pending.len() >= *max))
+        {
+            let line: Vec<u8> = self.pending.drain(..end).collect();
+            self.write_entry(&line)?;
+        }
+        Ok(())
+    }
+
+    /// Log the incomplete line left, if any
+    pub fn flush(&mut self) -> Result<(), LogError> {
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.write_entry(&line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Capture the output written to a pipe into a log, echoing it to the matching standard stream
+///
+/// The capture ends once every process holding the write end of the pipe has closed it.
+pub fn capture(fd: RawFd, stream: LogStream, writer: Arc<Mutex<LogWriter>>) -> JoinHandle<()> {
+    let pipe = unsafe { File::from_raw_fd(fd) };
+
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            match (&mut reader)
+                .take(MAX_LINE_LENGTH as u64)
+                .read_until(b'\n', &mut line)
+            {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            // The output is echoed even when the log cannot be written
+            let _ = match stream {
+                LogStream::Stdout => std::io::stdout().write_all(&line),
+                LogStream::Stderr => std::io::stderr().write_all(&line),
+            };
+
+            let log_result = writer
+                .lock()
+                .map_err(|_| LogError::CannotWriteLog(std::io::ErrorKind::Other.into()))
+                .and_then(|mut writer| writer.write_entry(stream, &String::from_utf8_lossy(&line)));
+            if let Err(e) = log_result {
+                eprintln!("warning: {}", e);
+                break;
+            }
+        }
+
+        // Keep draining the pipe so that the container never blocks on a full pipe
+        let _ = std::io::copy(&mut reader, &mut std::io::sink());
+    })
+}
+
+/// Structure representing a handle over the log of a container, opened for reading
+pub struct LogReader {
+    path: PathBuf,
+}
+
+impl LogReader {
+    /// Create a handle over the log at a given path
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Parse the entries of a log file from a reader, ignoring malformed lines
+    fn parse_entries(reader: impl BufRead) -> Result<Vec<LogEntry>, LogError> {
+        let mut entries = Vec::new();
+
+        for line in reader.lines() {
+            if let Ok(entry) = serde_json::from_str(&line.map_err(LogError::CannotReadLog)?) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Read all the entries of the log, from the oldest to the newest, along with the size of
+    /// the current log file that was read
+    pub fn entries(&self) -> Result<(Vec<LogEntry>, u64), LogError> {
+        let mut entries = Vec::new();
+
+        let rotated_count = (1..)
+            .take_while(|index| rotated_path(&self.path, *index).exists())
+            .count() as u32;
+        for index in (1..=rotated_count).rev() {
+            if let Ok(file) = File::open(rotated_path(&self.path, index)) {
+                entries.extend(Self::parse_entries(BufReader::new(file))?);
+            }
+        }
+
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((entries, 0)),
+            Err(e) => return Err(LogError::CannotOpenLog(self.path.display().to_string(), e)),
+        };
+        // Only complete lines are considered as read, the rest is left to be followed
+        let mut content = Vec::new();
+        (&file)
+            .read_to_end(&mut content)
+            .map_err(LogError::CannotReadLog)?;
+        let read_size = content
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |position| position + 1);
+        entries.extend(Self::parse_entries(&content[..read_size])?);
+
+        Ok((entries, read_size as u64))
+    }
+
+    /// Open the current log file at a given offset, along with its inode to detect rotations
+    fn open_at(&self, offset: u64) -> Result<Option<(BufReader<File>, u64)>, LogError> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(LogError::CannotOpenLog(self.path.display().to_string(), e)),
+        };
+        let inode = file.metadata().map_err(LogError::CannotReadLog)?.ino();
+        file.seek(SeekFrom::Start(offset))
+            .map_err(LogError::CannotReadLog)?;
+
+        Ok(Some((BufReader::new(file), inode)))
+    }
+
+    /// Follow the entries appended to the log after a given offset in the current log file, as
+    /// long as the container is running
+    pub fn follow(
+        &self,
+        offset: u64,
+        is_running: impl Fn() -> bool,
+        mut handle_entry: impl FnMut(LogEntry),
+    ) -> Result<(), LogError> {
+        let mut current = self.open_at(offset)?;
+        let mut line = String::new();
+
+        loop {
+            // The state is checked before reading, so that nothing written before the container
+            // exited or before the log was rotated can be missed
+            let running = is_running();
+            let current_inode = fs::metadata(&self.path).ok().map(|metadata| metadata.ino());
+
+            if let Some((reader, _)) = &mut current {
+                loop {
+                    let read = reader
+                        .read_line(&mut line)
+                        .map_err(LogError::CannotReadLog)?;
+                    if read == 0 || !line.ends_with('\n') {
+                        break;
+                    }
+                    if let Ok(entry) = serde_json::from_str(&line) {
+                        handle_entry(entry);
+                    }
+                    line.clear();
+                }
+            }
+
+            let rotated = match (&current, current_inode) {
+                (Some((_, inode)), Some(current_inode)) => *inode != current_inode,
+                (None, Some(_)) => true,
+                _ => false,
+            };
+            if rotated {
+                current = self.open_at(0)?;
+                line.clear();
+            } else if !running {
+                return Ok(());
+            } else {
+                thread::sleep(FOLLOW_INTERVAL);
+            }
+        }
+    }
+}
@@ -1,40 +1,76 @@
 use std::path::{Path, PathBuf};
 
+pub mod attach;
+pub mod blob;
+pub mod cgroup;
 pub mod container;
+pub mod docker;
 pub mod image;
+pub mod init;
+pub mod layer;
+pub mod logs;
+pub mod monitor;
+pub mod network;
+pub mod oci;
+pub mod resources;
+pub mod state;
+pub mod tty;
+pub mod user;
+pub mod userns;
+pub mod volume;
 
 pub struct Config {
+    blob_store_path: PathBuf,
     container_store_path: PathBuf,
-    extracted_image_store_path: PathBuf,
     image_store_path: PathBuf,
+    layer_store_path: PathBuf,
+    volume_store_path: PathBuf,
 }
 
 impl Config {
     /// Create a new configuration from a base directory
     pub fn new(base_dir: &Path) -> Self {
+        let blob_store_path = base_dir.join("blobs");
         let container_store_path = base_dir.join("containers");
-        let extracted_image_store_path = base_dir.join("extracted_images");
         let image_store_path = base_dir.join("images");
+        let layer_store_path = base_dir.join("layers");
+        let volume_store_path = base_dir.join("volumes");
 
         Self {
+            blob_store_path,
             container_store_path,
-            extracted_image_store_path,
             image_store_path,
+            layer_store_path,
+            volume_store_path,
         }
     }
 
     /// Obtain a handle over the image store
     pub fn image_store(&self) -> image::ImageStore {
-        image::ImageStore::from_directory(&self.image_store_path)
+        image::ImageStore::from_directory(
+            &self.image_store_path,
+            self.blob_store(),
+            self.layer_store(),
+        )
     }
 
-    /// Obtain a handle over the extracted image store
-    pub fn extracted_image_store(&self) -> image::ExtractedImageStore {
-        image::ExtractedImageStore::from_directory(&self.extracted_image_store_path)
+    /// Obtain a handle over the blob store
+    pub fn blob_store(&self) -> blob::BlobStore {
+        blob::BlobStore::from_directory(&self.blob_store_path)
+    }
+
+    /// Obtain a handle over the layer store
+    pub fn layer_store(&self) -> layer::LayerStore {
+        layer::LayerStore::from_directory(&self.layer_store_path)
     }
 
     /// Obtain a handle over the container store
     pub fn container_store(&self) -> container::ContainerStore {
         container::ContainerStore::from_directory(&self.container_store_path)
     }
+
+    /// Obtain a handle over the volume store
+    pub fn volume_store(&self) -> volume::VolumeStore {
+        volume::VolumeStore::from_directory(&self.volume_store_path)
+    }
 }
@@ -0,0 +1,116 @@
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use failure::Fail;
+use nix::fcntl::{open, OFlag};
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::sys::stat::Mode;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{chdir, close, dup2, fork, setsid, ForkResult};
+
+use super::attach::AttachServer;
+use super::container::{Container, ContainerError};
+use super::logs::{LogStream, LogWriter};
+use super::Config;
+
+/// Error type for monitor-related errors
+#[derive(Fail, Debug)]
+pub enum MonitorError {
+    /// The monitor process could not be created
+    #[fail(display = "cannot fork the monitor process: {}", _0)]
+    CannotFork(nix::Error),
+
+    /// The monitor process could not be detached from the caller
+    #[fail(display = "cannot detach the monitor process")]
+    CannotDetach,
+}
+
+/// Redirect the standard streams to `/dev/null`, so that the monitor does not hold the terminal
+fn redirect_standard_streams() -> Result<(), nix::Error> {
+    let null_fd = open(Path::new("/dev/null"), OFlag::O_RDWR, Mode::empty())?;
+
+    for fd in &[0 as RawFd, 1, 2] {
+        dup2(null_fd, *fd)?;
+    }
+    if null_fd > 2 {
+        close(null_fd)?;
+    }
+    Ok(())
+}
+
+/// Write an error of the monitor to the log of the container, the only place where it can be
+/// found since the standard streams are redirected
+fn log_error(container: &Container, message: &str) {
+    let _ = LogWriter::open(
+        container.log_path(),
+        container.config().log_config().clone(),
+    )
+    .and_then(|mut log| log.write_entry(LogStream::Stderr, &format!("{}\n", message)));
+}
+
+/// Supervise a container until it exits, which records its exit status in its state
+fn monitor(config: &Config, container: &mut Container, args: &[String]) -> ! {
+    let setup_result = unsafe { signal(Signal::SIGHUP, SigHandler::SigIgn) }
+        .and_then(|_| chdir(Path::new("/")))
+        .and_then(|_| redirect_standard_streams());
+    if setup_result.is_err() {
+        std::process::exit(1);
+    }
+
+    // The standard streams of the container are served to the clients attaching to it
+    let attach_server = AttachServer::start(
+        &container.attach_socket_path(),
+        container.config().open_stdin(),
+    );
+    match attach_server {
+        Ok(ref attach_server) => container.set_window_sizes(attach_server.window_sizes()),
+        Err(ref e) => log_error(container, &format!("warning: {}", e)),
+    }
+
+    let result = container.run_command(config, args);
+    if let Ok(attach_server) = attach_server {
+        attach_server.stop();
+    }
+
+    let exit_code = match result {
+        Ok(()) => 0,
+        Err(ContainerError::CommandExitedWithError(_)) => 1,
+        Err(e) => {
+            log_error(container, &format!("error: {}", e));
+            1
+        }
+    };
+    if container.config().auto_remove() {
+        let container_store = config.container_store();
+        if let Some(container) = container_store.get_container(container.name()) {
+            let _ = container_store.remove_container(container);
+        }
+    }
+    std::process::exit(exit_code)
+}
+
+/// Run a command in a container supervised by a monitor process, returning immediately
+///
+/// The monitor is forked twice and leads its own session, so that it is reparented to init and
+/// survives both the exit of the caller and the hangup of its terminal.
+pub fn run_detached(
+    config: &Config,
+    container: &mut Container,
+    args: &[String],
+) -> Result<(), MonitorError> {
+    match fork().map_err(MonitorError::CannotFork)? {
+        ForkResult::Parent { child } => match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, 0)) => Ok(()),
+            _ => Err(MonitorError::CannotDetach),
+        },
+        ForkResult::Child => {
+            // The intermediate process only creates the session and exits once the monitor
+            // is forked, so that the monitor cannot acquire a controlling terminal again
+            match setsid().and_then(|_| fork()) {
+                Ok(ForkResult::Parent { .. }) => std::process::exit(0),
+                Ok(ForkResult::Child) => monitor(config, container, args),
+                Err(_) => std::process::exit(1),
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::fs;
+use std::net::Ipv4Addr;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use failure::Fail;
+use nix::fcntl::{flock, FlockArg};
+use nix::sched::{setns, CloneFlags};
+use nix::unistd::Pid;
+use serde_derive::{Deserialize, Serialize};
+
+use super::container::ContainerStore;
+
+/// Name of the bridge host-side interfaces are attached to
+pub const BRIDGE_NAME: &str = "jocker0";
+
+/// Address of the bridge, used as the default gateway by containers
+pub const BRIDGE_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 29, 0, 1);
+
+/// Prefix length of the subnet containers get their addresses from
+pub const SUBNET_PREFIX_LENGTH: u8 = 16;
+
+/// Error type for network-related errors
+#[derive(Fail, Debug)]
+pub enum NetworkError {
+    /// The given network mode does not exist
+    #[fail(display = "invalid network mode: {}", _0)]
+    InvalidNetworkMode(String),
+
+    /// The bridge networking mode was requested by an unprivileged user
+    #[fail(display = "bridge networking requires root privileges")]
+    BridgeRequiresPrivileges,
+
+    /// No address is left in the managed subnet
+    #[fail(display = "no address left in the container subnet")]
+    AddressPoolExhausted,
+
+    /// The existing containers could not be listed to allocate an address
+    #[fail(display = "cannot list existing containers: {}", _0)]
+    CannotListContainers(std::io::Error),
+
+    /// The addresses of the containers could not be locked to allocate one
+    #[fail(display = "cannot lock the container addresses: {}", _0)]
+    CannotLockAddresses(nix::Error),
+
+    /// The network namespace of the container could not be opened
+    #[fail(display = "cannot open the network namespace: {}", _0)]
+    CannotOpenNamespace(std::io::Error),
+
+    /// A network configuration command could not be executed
+    #[fail(display = "cannot execute \"{}\": {}", _0, _1)]
+    CannotRunCommand(String, std::io::Error),
+
+    /// A network configuration command exited with an error
+    #[fail(display = "\"{}\" failed", _0)]
+    CommandFailed(String),
+}
+
+/// Enumeration for the networking modes available to containers
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// The container has its own network stack, connected to the jocker bridge
+    Bridge,
+    /// The container shares the network stack of the host
+    Host,
+    /// The container has its own network stack, with only a loopback interface
+    None,
+}
+
+impl Default for NetworkMode {
+    fn default() -> Self {
+        NetworkMode::Bridge
+    }
+}
+
+impl FromStr for NetworkMode {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bridge" => Ok(NetworkMode::Bridge),
+            "host" => Ok(NetworkMode::Host),
+            "none" => Ok(NetworkMode::None),
+            _ => Err(NetworkError::InvalidNetworkMode(s.to_string())),
+        }
+    }
+}
+
+impl NetworkMode {
+    /// Retrieve the flags to pass to `clone` for this networking mode
+    pub fn clone_flags(self) -> CloneFlags {
+        match self {
+            NetworkMode::Host => CloneFlags::empty(),
+            NetworkMode::Bridge | NetworkMode::None => CloneFlags::CLONE_NEWNET,
+        }
+    }
+}
+
+/// Run a command, optionally inside the network namespace of a given process
+fn run(args: &[&str], netns_pid: Option<Pid>) -> Result<(), NetworkError> {
+    let description = args.join(" ");
+    let mut command = Command::new(args[0]);
+    command.args(&args[1..]);
+
+    // Keep the namespace file open until the command has been spawned
+    let netns = if let Some(pid) = netns_pid {
+        let file = fs::File::open(format!("/proc/{}/ns/net", pid))
+            .map_err(NetworkError::CannotOpenNamespace)?;
+        let fd = file.as_raw_fd();
+        unsafe {
+            command.pre_exec(move || {
+                setns(fd, CloneFlags::CLONE_NEWNET).map_err(|_| std::io::Error::last_os_error())
+            });
+        }
+        Some(file)
+    } else {
+        None
+    };
+
+    let status = command
+        .status()
+        .map_err(|e| NetworkError::CannotRunCommand(description.clone(), e))?;
+    drop(netns);
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(NetworkError::CommandFailed(description))
+    }
+}
+
+/// Create the jocker bridge and allow containers to reach the outside world, if not done already
+///
+/// The bridge is deleted if it cannot be configured, so that the configuration is attempted
+/// again by the next container.
+pub fn setup_bridge() -> Result<(), NetworkError> {
+    if Path::new("/sys/class/net").join(BRIDGE_NAME).exists() {
+        return Ok(());
+    }
+
+    run(&["ip", "link", "add", BRIDGE_NAME, "type", "bridge"], None)?;
+    let result = configure_bridge();
+    if result.is_err() {
+        let _ = run(&["ip", "link", "delete", BRIDGE_NAME], None);
+    }
+    result
+}
+
+/// Give the freshly created jocker bridge its address, and masquerade the traffic of the
+/// containers
+fn configure_bridge() -> Result<(), NetworkError> {
+    let bridge_address = format!("{}/{}", BRIDGE_ADDRESS, SUBNET_PREFIX_LENGTH);
+    let subnet = format!("{}/{}", subnet_address(), SUBNET_PREFIX_LENGTH);
+
+    run(
+        &["ip", "addr", "add", &bridge_address, "dev", BRIDGE_NAME],
+        None,
+    )?;
+    run(&["ip", "link", "set", BRIDGE_NAME, "up"], None)?;
+
+    // Forward and masquerade the traffic coming from the containers
+    fs::write("/proc/sys/net/ipv4/ip_forward", "1")
+        .map_err(|e| NetworkError::CannotRunCommand("enable IPv4 forwarding".to_string(), e))?;
+    let nat_rule = [
+        "POSTROUTING",
+        "-s",
+        &subnet,
+        "!",
+        "-o",
+        BRIDGE_NAME,
+        "-j",
+        "MASQUERADE",
+    ];
+    let check_rule = [&["iptables", "-t", "nat", "-C"][..], &nat_rule[..]].concat();
+    if run(&check_rule, None).is_err() {
+        let append_rule = [&["iptables", "-t", "nat", "-A"][..], &nat_rule[..]].concat();
+        run(&append_rule, None)?;
+    }
+
+    Ok(())
+}
+
+/// Retrieve the address of the subnet managed by jocker
+fn subnet_address() -> Ipv4Addr {
+    let mask = !0u32 << (32 - SUBNET_PREFIX_LENGTH);
+
+    Ipv4Addr::from(u32::from(BRIDGE_ADDRESS) & mask)
+}
+
+/// Structure holding the lock which serializes the allocation of addresses, released when dropped
+pub struct AddressLock {
+    _directory: fs::File,
+}
+
+/// Find an address of the managed subnet that is not used by any existing container
+///
+/// The address is only reserved once it is saved in the configuration of its container, so the
+/// returned lock must be held until then, for other containers not to be given the same one.
+pub fn allocate_address(
+    container_store: &ContainerStore,
+) -> Result<(Ipv4Addr, AddressLock), NetworkError> {
+    let directory =
+        fs::File::open(container_store.path()).map_err(NetworkError::CannotListContainers)?;
+    flock(directory.as_raw_fd(), FlockArg::LockExclusive)
+        .map_err(NetworkError::CannotLockAddresses)?;
+    let lock = AddressLock {
+        _directory: directory,
+    };
+
+    let mut used = vec![BRIDGE_ADDRESS];
+    let containers = container_store
+        .containers()
+        .map_err(NetworkError::CannotListContainers)?;
+    for container in containers.flatten() {
+        used.extend(container.config().ip_address());
+    }
+
+    let first = u32::from(subnet_address()) + 1;
+    let broadcast = u32::from(subnet_address()) | !(!0u32 << (32 - SUBNET_PREFIX_LENGTH));
+
+    let address = (first..broadcast)
+        .map(Ipv4Addr::from)
+        .find(|address| !used.contains(address))
+        .ok_or(NetworkError::AddressPoolExhausted)?;
+    Ok((address, lock))
+}
+
+/// Bring up the loopback interface in the network namespace of a process
+pub fn setup_loopback(pid: Pid) -> Result<(), NetworkError> {
+    run(&["ip", "link", "set", "lo", "up"], Some(pid))
+}
+
+/// Connect the network namespace of a process to the jocker bridge through a veth pair
+///
+/// The pair is deleted if it cannot be configured, so that no interface is left on the host.
+pub fn setup_veth_pair(pid: Pid, address: Ipv4Addr) -> Result<(), NetworkError> {
+    let host_interface = format!("vethj{}", pid);
+    let container_interface = format!("vethc{}", pid);
+
+    // Create the pair, whose ends are deleted together
+    run(
+        &[
+            "ip",
+            "link",
+            "add",
+            &host_interface,
+            "type",
+            "veth",
+            "peer",
+            "name",
+            &container_interface,
+        ],
+        None,
+    )?;
+    let result = configure_veth_pair(pid, address, &host_interface, &container_interface);
+    if result.is_err() {
+        let _ = run(&["ip", "link", "delete", &host_interface], None);
+    }
+    result
+}
+
+/// Move one end of a freshly created veth pair into the network namespace of a process, and
+/// attach the other one to the jocker bridge
+fn configure_veth_pair(
+    pid: Pid,
+    address: Ipv4Addr,
+    host_interface: &str,
+    container_interface: &str,
+) -> Result<(), NetworkError> {
+    let pid_string = pid.to_string();
+    let address = format!("{}/{}", address, SUBNET_PREFIX_LENGTH);
+    let gateway = BRIDGE_ADDRESS.to_string();
+
+    run(
+        &[
+            "ip",
+            "link",
+            "set",
+            container_interface,
+            "netns",
+            &pid_string,
+        ],
+        None,
+    )?;
+
+    // Attach the host end to the bridge
+    run(
+        &["ip", "link", "set", host_interface, "master", BRIDGE_NAME],
+        None,
+    )?;
+    run(&["ip", "link", "set", host_interface, "up"], None)?;
+
+    // Configure the container end
+    run(
+        &["ip", "link", "set", container_interface, "name", "eth0"],
+        Some(pid),
+    )?;
+    run(&["ip", "addr", "add", &address, "dev", "eth0"], Some(pid))?;
+    run(&["ip", "link", "set", "eth0", "up"], Some(pid))?;
+    run(
+        &["ip", "route", "add", "default", "via", &gateway],
+        Some(pid),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,453 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use failure::Fail;
+use serde_derive::{Deserialize, Serialize};
+use tar::{Archive, EntryType, Header};
+
+use super::blob::{digest_hex, BlobError, BlobStore};
+use super::image::{
+    Descriptor, Image, ImageError, ImageStore, Manifest, LAYER_MEDIA_TYPE, MANIFEST_MEDIA_TYPE,
+};
+use super::layer;
+
+/// Name of the file marking a directory as an image layout
+pub const LAYOUT_FILE: &str = "oci-layout";
+
+/// Name of the file listing the images of a layout
+const INDEX_FILE: &str = "index.json";
+
+/// Version of the image layout specification written by jocker
+const LAYOUT_VERSION: &str = "1.0.0";
+
+/// Annotation of the index giving the name of an image
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// Media type of the manifests written by Docker, which have the same structure
+const DOCKER_MANIFEST_MEDIA_TYPE: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// Media types of the gzip-compressed layers, which can be used as they are
+const GZIP_LAYER_MEDIA_TYPES: &[&str] = &[
+    LAYER_MEDIA_TYPE,
+    "application/vnd.docker.image.rootfs.diff.tar.gzip",
+];
+
+/// Media types of the uncompressed layers, which are compressed when imported
+const TAR_LAYER_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.layer.v1.tar",
+    "application/vnd.docker.image.rootfs.diff.tar",
+];
+
+/// Error type for errors related to image layouts
+#[derive(Fail, Debug)]
+pub enum OciError {
+    /// The layout is missing a file, or uses an unsupported version
+    #[fail(display = "invalid image layout: {}", _0)]
+    InvalidLayout(String),
+
+    /// The index or a manifest of the layout could not be parsed
+    #[fail(display = "invalid image layout metadata: {}", _0)]
+    InvalidMetadata(serde_json::Error),
+
+    /// The layout has no image with the requested name
+    #[fail(display = "no image named {} in the layout", _0)]
+    ImageNotFound(String),
+
+    /// The layout has several images, none of them with the requested name
+    #[fail(display = "several images in the layout, none named {}", _0)]
+    AmbiguousImage(String),
+
+    /// A manifest or a layer of the layout has an unsupported type
+    #[fail(display = "unsupported media type {}", _0)]
+    UnsupportedMediaType(String),
+
+    /// The layout could not be read
+    #[fail(display = "unable to read image layout: {}", _0)]
+    CannotReadLayout(std::io::Error),
+
+    /// The layout could not be written
+    #[fail(display = "unable to write image layout: {}", _0)]
+    CannotWriteLayout(std::io::Error),
+
+    /// A blob of the layout could not be stored or used
+    #[fail(display = "{}", _0)]
+    BlobError(BlobError),
+
+    /// The image could not be stored or used
+    #[fail(display = "{}", _0)]
+    ImageError(ImageError),
+}
+
+/// Content of the file marking a directory as an image layout
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ImageLayout {
+    image_layout_version: String,
+}
+
+/// Reference to a manifest in an index, along with its annotations
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexEntry {
+    #[serde(flatten)]
+    descriptor: Descriptor,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+}
+
+impl IndexEntry {
+    /// Retrieve the name of the image referencing the manifest, if it has one
+    fn ref_name(&self) -> Option<&str> {
+        self.annotations
+            .get(REF_NAME_ANNOTATION)
+            .map(String::as_str)
+    }
+}
+
+/// Index listing the manifests of the images of a layout
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    schema_version: u32,
+    manifests: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Select the manifest of the image with a given name, or the only manifest of the index
+    fn select(&self, name: &str) -> Result<&IndexEntry, OciError> {
+        match self.manifests.as_slice() {
+            [entry] => Ok(entry),
+            entries => entries
+                .iter()
+                .find(|entry| entry.ref_name() == Some(name))
+                .ok_or_else(|| {
+                    if entries.is_empty() {
+                        OciError::ImageNotFound(name.to_string())
+                    } else {
+                        OciError::AmbiguousImage(name.to_string())
+                    }
+                }),
+        }
+    }
+}
+
+/// Location of the blobs of a layout being imported
+enum LayoutSource<'a> {
+    /// The blobs are in the directory of the layout
+    Directory(&'a Path),
+    /// The blobs were all stored while reading a tarball
+    Tarball,
+}
+
+impl<'a> LayoutSource<'a> {
+    /// Make sure the blob a descriptor references is in a blob store
+    fn fetch(&self, blob_store: &BlobStore, descriptor: &Descriptor) -> Result<(), OciError> {
+        let digest = descriptor.digest();
+        let blob_path = blob_store.blob_path(digest).map_err(OciError::BlobError)?;
+        if blob_path.exists() {
+            return Ok(());
+        }
+
+        match self {
+            LayoutSource::Directory(path) => {
+                let hex = digest_hex(digest).map_err(OciError::BlobError)?;
+                let path = path.join("blobs").join("sha256").join(hex);
+                if !path.exists() {
+                    return Err(OciError::BlobError(BlobError::BlobNotFound(
+                        digest.to_string(),
+                    )));
+                }
+                blob_store
+                    .put_file(&path, Some(digest))
+                    .map_err(OciError::BlobError)?;
+                Ok(())
+            }
+            LayoutSource::Tarball => Err(OciError::BlobError(BlobError::BlobNotFound(
+                digest.to_string(),
+            ))),
+        }
+    }
+}
+
+/// Parse the JSON content of a file of a layout
+fn parse_json<T: serde::de::DeserializeOwned>(
+    name: &str,
+    content: Option<Vec<u8>>,
+) -> Result<T, OciError> {
+    let content = content.ok_or_else(|| OciError::InvalidLayout(format!("missing {}", name)))?;
+
+    serde_json::from_slice(&content).map_err(OciError::InvalidMetadata)
+}
+
+/// Read the files of a layout stored in a directory
+fn read_directory(path: &Path) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), OciError> {
+    let read = |name| match fs::read(path.join(name)) {
+        Ok(content) => Ok(Some(content)),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(OciError::CannotReadLayout(e)),
+    };
+
+    Ok((read(LAYOUT_FILE)?, read(INDEX_FILE)?))
+}
+
+/// Read the whole content of an entry of a tarball
+fn read_entry<R: Read>(entry: &mut R) -> Result<Vec<u8>, OciError> {
+    let mut content = Vec::new();
+
+    entry
+        .read_to_end(&mut content)
+        .map_err(OciError::CannotReadLayout)?;
+    Ok(content)
+}
+
+/// Read the files of a layout stored in a tarball, possibly gzip-compressed, storing all its
+/// blobs in a blob store since the entries of a tarball can only be read in order
+fn read_tarball(
+    blob_store: &BlobStore,
+    path: &Path,
+) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), OciError> {
+    let file = fs::File::open(path).map_err(OciError::CannotReadLayout)?;
+    let reader = layer::decompress(file).map_err(OciError::CannotReadLayout)?;
+
+    let mut layout = None;
+    let mut index = None;
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries().map_err(OciError::CannotReadLayout)? {
+        let mut entry = entry.map_err(OciError::CannotReadLayout)?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let entry_path = entry
+            .path()
+            .map_err(OciError::CannotReadLayout)?
+            .into_owned();
+        let components = entry_path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>();
+
+        match components.as_deref() {
+            Some([LAYOUT_FILE]) => layout = Some(read_entry(&mut entry)?),
+            Some([INDEX_FILE]) => index = Some(read_entry(&mut entry)?),
+            Some(["blobs", algorithm, hex]) => {
+                // Blobs with unsupported digests are only an error if they are used
+                let digest = format!("{}:{}", algorithm, hex);
+                if digest_hex(&digest).is_ok() {
+                    blob_store
+                        .put(&mut entry, Some(&digest))
+                        .map_err(OciError::BlobError)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok((layout, index))
+}
+
+/// Import an image from an image layout stored in a directory or in a tarball
+///
+/// When the layout holds several images, the one whose `org.opencontainers.image.ref.name`
+/// annotation is the given name is imported. Uncompressed layers are compressed, the other
+/// blobs are imported as they are.
+pub fn import_layout(
+    image_store: &ImageStore,
+    name: String,
+    path: &Path,
+) -> Result<Image, OciError> {
+    let blob_store = image_store.blob_store();
+    let (source, (layout, index)) = if path.is_dir() {
+        (LayoutSource::Directory(path), read_directory(path)?)
+    } else {
+        (LayoutSource::Tarball, read_tarball(blob_store, path)?)
+    };
+
+    let layout: ImageLayout = parse_json(LAYOUT_FILE, layout)?;
+    if !layout.image_layout_version.starts_with("1.") {
+        return Err(OciError::InvalidLayout(format!(
+            "unsupported version {}",
+            layout.image_layout_version
+        )));
+    }
+    let index: Index = parse_json(INDEX_FILE, index)?;
+    let entry = index.select(&name)?;
+
+    let media_type = entry.descriptor.media_type();
+    if media_type != MANIFEST_MEDIA_TYPE && media_type != DOCKER_MANIFEST_MEDIA_TYPE {
+        return Err(OciError::UnsupportedMediaType(media_type.to_string()));
+    }
+    source.fetch(blob_store, &entry.descriptor)?;
+    let manifest = blob_store
+        .read(entry.descriptor.digest())
+        .map_err(OciError::BlobError)?;
+    let manifest: Manifest =
+        serde_json::from_slice(&manifest).map_err(OciError::InvalidMetadata)?;
+    source.fetch(blob_store, manifest.config())?;
+    image_store
+        .config(&manifest)
+        .map_err(OciError::ImageError)?;
+
+    // The manifest is only rewritten if some of its layers have to be converted
+    let mut layers = Vec::new();
+    let mut converted = false;
+    for layer in manifest.layers() {
+        source.fetch(blob_store, layer)?;
+
+        let media_type = layer.media_type();
+        if media_type == LAYER_MEDIA_TYPE {
+            layers.push(layer.clone());
+        } else if GZIP_LAYER_MEDIA_TYPES.contains(&media_type) {
+            layers.push(Descriptor::new(
+                LAYER_MEDIA_TYPE,
+                layer.digest().to_string(),
+                layer.size(),
+            ));
+            converted = true;
+        } else if TAR_LAYER_MEDIA_TYPES.contains(&media_type) {
+            let mut reader = blob_store
+                .open(layer.digest())
+                .map_err(OciError::BlobError)?;
+            let (descriptor, _) = image_store
+                .put_layer(&mut reader)
+                .map_err(OciError::ImageError)?;
+            reader.verify(layer.digest()).map_err(OciError::BlobError)?;
+            layers.push(descriptor);
+            converted = true;
+        } else {
+            return Err(OciError::UnsupportedMediaType(media_type.to_string()));
+        }
+    }
+
+    if converted {
+        image_store.create_manifest(name, manifest.config().clone(), layers)
+    } else {
+        image_store.save_image(name, entry.descriptor.digest().to_string())
+    }
+    .map_err(OciError::ImageError)
+}
+
+/// Destination of the files of a layout being exported
+enum LayoutWriter {
+    /// The files are written to a directory
+    Directory(PathBuf),
+    /// The files are appended to a tarball
+    Tarball(tar::Builder<fs::File>),
+}
+
+impl LayoutWriter {
+    /// Write a file of a layout, at a path relative to its root
+    fn write<R: Read>(&mut self, path: &Path, mut reader: R, size: u64) -> std::io::Result<()> {
+        match self {
+            LayoutWriter::Directory(layout_path) => {
+                let path = layout_path.join(path);
+                if let Some(parent_path) = path.parent() {
+                    fs::create_dir_all(parent_path)?;
+                }
+                let mut file = fs::File::create(&path)?;
+                std::io::copy(&mut reader, &mut file)?;
+                file.sync_all()
+            }
+            LayoutWriter::Tarball(tar) => {
+                let mut header = Header::new_gnu();
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(size);
+                header.set_mode(0o644);
+                tar.append_data(&mut header, path, reader)
+            }
+        }
+    }
+
+    /// Write a JSON file of a layout
+    fn write_json<T: serde::Serialize>(&mut self, path: &str, value: &T) -> Result<(), OciError> {
+        let content = serde_json::to_vec(value).map_err(OciError::InvalidMetadata)?;
+
+        self.write(Path::new(path), &content[..], content.len() as u64)
+            .map_err(OciError::CannotWriteLayout)
+    }
+
+    /// Copy a blob from a blob store to a layout, after checking its digest
+    fn write_blob(&mut self, blob_store: &BlobStore, digest: &str) -> Result<(), OciError> {
+        let hex = digest_hex(digest).map_err(OciError::BlobError)?;
+        let size = blob_store
+            .blob_path(digest)
+            .and_then(|path| fs::metadata(path).map_err(BlobError::CannotReadBlob))
+            .map_err(OciError::BlobError)?
+            .len();
+        let mut reader = blob_store.open(digest).map_err(OciError::BlobError)?;
+
+        let path = Path::new("blobs").join("sha256").join(hex);
+        self.write(&path, &mut reader, size)
+            .map_err(OciError::CannotWriteLayout)?;
+        reader.verify(digest).map_err(OciError::BlobError)
+    }
+
+    /// Finish writing the layout
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            LayoutWriter::Directory(_) => Ok(()),
+            LayoutWriter::Tarball(tar) => tar.into_inner()?.sync_all(),
+        }
+    }
+}
+
+/// Export an image as an image layout, written to a tarball if the path ends with `.tar` and to
+/// a directory otherwise
+pub fn export_layout(image_store: &ImageStore, image: &Image, path: &Path) -> Result<(), OciError> {
+    let blob_store = image_store.blob_store();
+    let manifest_digest = image.manifest_digest().map_err(OciError::ImageError)?;
+    let manifest = image_store.manifest(image).map_err(OciError::ImageError)?;
+    let manifest_size = blob_store
+        .read(&manifest_digest)
+        .map_err(OciError::BlobError)?
+        .len();
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(REF_NAME_ANNOTATION.to_string(), image.name());
+    let index = Index {
+        schema_version: 2,
+        manifests: vec![IndexEntry {
+            descriptor: Descriptor::new(
+                manifest.media_type().unwrap_or(MANIFEST_MEDIA_TYPE),
+                manifest_digest.clone(),
+                manifest_size as u64,
+            ),
+            annotations,
+        }],
+    };
+
+    let is_tarball = path
+        .extension()
+        .map_or(false, |extension| extension == "tar");
+    let mut writer = if is_tarball {
+        let file = fs::File::create(path).map_err(OciError::CannotWriteLayout)?;
+        LayoutWriter::Tarball(tar::Builder::new(file))
+    } else {
+        fs::create_dir_all(path).map_err(OciError::CannotWriteLayout)?;
+        LayoutWriter::Directory(path.to_path_buf())
+    };
+
+    let write_result: Result<(), OciError> = try {
+        writer.write_json(
+            LAYOUT_FILE,
+            &ImageLayout {
+                image_layout_version: LAYOUT_VERSION.to_string(),
+            },
+        )?;
+        writer.write_json(INDEX_FILE, &index)?;
+
+        writer.write_blob(blob_store, &manifest_digest)?;
+        writer.write_blob(blob_store, manifest.config().digest())?;
+        for layer in manifest.layers() {
+            writer.write_blob(blob_store, layer.digest())?;
+        }
+        writer.finish().map_err(OciError::CannotWriteLayout)?;
+    };
+    // An incomplete tarball is useless, unlike the blobs already written to a directory
+    if write_result.is_err() && is_tarball {
+        let _ = fs::remove_file(path);
+    }
+
+    write_result
+}
@@ -0,0 +1,363 @@
+use failure::Fail;
+use nix::sys::stat::{major, minor, stat, SFlag};
+use serde_derive::{Deserialize, Serialize};
+
+/// Error type for resource limit-related errors
+#[derive(Fail, Debug)]
+pub enum ResourcesError {
+    /// A size could not be parsed
+    #[fail(display = "invalid size {}", _0)]
+    InvalidSize(String),
+
+    /// A limit was given a value outside of its allowed range
+    #[fail(display = "invalid value for {}: {}", _0, _1)]
+    InvalidValue(&'static str, String),
+
+    /// The swap limit was given without a memory limit
+    #[fail(display = "a memory limit is required to limit the swap")]
+    SwapLimitWithoutMemoryLimit,
+
+    /// A device rate was not given as `/dev/path:rate`
+    #[fail(display = "invalid device rate {}", _0)]
+    InvalidDeviceRate(String),
+
+    /// A throttled device is not a block device of the host
+    #[fail(display = "{} is not a block device", _0)]
+    InvalidDevice(String),
+}
+
+/// Parse a size given in bytes, optionally followed by a unit (`b`, `k`, `m` or `g`)
+pub fn parse_size(s: &str) -> Result<u64, ResourcesError> {
+    let s = s.trim().to_ascii_lowercase();
+    let (number, multiplier) = match s.chars().last() {
+        Some('b') => (&s[..s.len() - 1], 1),
+        Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (&s[..], 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| ResourcesError::InvalidSize(s.to_string()))
+}
+
+/// Structure describing a limit on the I/O rate of a block device
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct DeviceRate {
+    major: u64,
+    minor: u64,
+    rate: u64,
+}
+
+impl DeviceRate {
+    /// Parse a rate given as `/dev/path:rate`, using a given function to parse the rate
+    fn parse(spec: &str, parse_rate: impl Fn(&str) -> Option<u64>) -> Result<Self, ResourcesError> {
+        let mut pieces = spec.rsplitn(2, ':');
+        let rate = pieces.next().and_then(parse_rate);
+        let (path, rate) = match (pieces.next(), rate) {
+            (Some(path), Some(rate)) => (path, rate),
+            _ => return Err(ResourcesError::InvalidDeviceRate(spec.to_string())),
+        };
+
+        let file_stat = stat(path).map_err(|_| ResourcesError::InvalidDevice(path.to_string()))?;
+        if SFlag::from_bits_truncate(file_stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFBLK {
+            return Err(ResourcesError::InvalidDevice(path.to_string()));
+        }
+
+        Ok(Self {
+            major: major(file_stat.st_rdev),
+            minor: minor(file_stat.st_rdev),
+            rate,
+        })
+    }
+
+    /// Parse a rate given in bytes per second as `/dev/path:rate`, where the rate can have a unit
+    pub fn parse_bps(spec: &str) -> Result<Self, ResourcesError> {
+        Self::parse(spec, |rate| parse_size(rate).ok())
+    }
+
+    /// Parse a rate given in operations per second as `/dev/path:rate`
+    pub fn parse_iops(spec: &str) -> Result<Self, ResourcesError> {
+        Self::parse(spec, |rate| rate.parse().ok())
+    }
+
+    /// Retrieve the device as `major:minor`, which is how control groups refer to it
+    pub fn device(&self) -> String {
+        format!("{}:{}", self.major, self.minor)
+    }
+
+    /// Retrieve the rate
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+}
+
+/// Structure describing the limits on the resources a container can use
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Resources {
+    /// Maximum amount of memory, in bytes
+    #[serde(default)]
+    memory: Option<u64>,
+    /// Maximum amount of memory and swap, in bytes, or `None` to allow unlimited swap
+    #[serde(default)]
+    memory_swap: Option<u64>,
+    /// Tendency of the kernel to swap the memory of the container, from 0 to 100
+    #[serde(default)]
+    memory_swappiness: Option<u64>,
+    /// Relative weight of the container when CPUs are contended
+    #[serde(default)]
+    cpu_shares: Option<u64>,
+    /// Number of CPUs worth of time the container can use
+    #[serde(default)]
+    cpus: Option<f64>,
+    /// CPUs the container is allowed to run on, as a list such as `0-3,6`
+    #[serde(default)]
+    cpuset_cpus: Option<String>,
+    /// Memory nodes the container is allowed to use, as a list such as `0-3,6`
+    #[serde(default)]
+    cpuset_mems: Option<String>,
+    /// Maximum number of processes and threads in the container
+    #[serde(default)]
+    pids_limit: Option<u64>,
+    /// Relative weight of the container when block devices are contended, from 10 to 1000
+    #[serde(default)]
+    blkio_weight: Option<u64>,
+    /// Limits on the bytes read per second from block devices
+    #[serde(default)]
+    device_read_bps: Vec<DeviceRate>,
+    /// Limits on the bytes written per second to block devices
+    #[serde(default)]
+    device_write_bps: Vec<DeviceRate>,
+    /// Limits on the read operations per second on block devices
+    #[serde(default)]
+    device_read_iops: Vec<DeviceRate>,
+    /// Limits on the write operations per second on block devices
+    #[serde(default)]
+    device_write_iops: Vec<DeviceRate>,
+}
+
+/// Period of the CFS scheduler used to enforce CPU limits, in microseconds
+pub const CPU_PERIOD: u64 = 100_000;
+
+/// Check whether a string is a list of CPUs or memory nodes, such as `0-3,6`
+fn is_valid_cpuset(cpuset: &str) -> bool {
+    cpuset.split(',').all(|range| {
+        let mut bounds = range.splitn(2, '-');
+        let start = bounds.next().and_then(|start| start.parse::<u32>().ok());
+        match (start, bounds.next()) {
+            (Some(_), None) => true,
+            (Some(start), Some(end)) => end.parse::<u32>().map_or(false, |end| start <= end),
+            _ => false,
+        }
+    })
+}
+
+impl Resources {
+    /// Retrieve the memory limit, in bytes
+    pub fn memory(&self) -> Option<u64> {
+        self.memory
+    }
+
+    /// Retrieve the memory and swap limit, in bytes
+    pub fn memory_swap(&self) -> Option<u64> {
+        self.memory_swap
+    }
+
+    /// Retrieve the swappiness
+    pub fn memory_swappiness(&self) -> Option<u64> {
+        self.memory_swappiness
+    }
+
+    /// Set the memory limits, where the swap limit includes the memory limit, like in Docker
+    pub fn set_memory(
+        &mut self,
+        memory: Option<u64>,
+        memory_swap: Option<u64>,
+    ) -> Result<(), ResourcesError> {
+        match (memory, memory_swap) {
+            (None, Some(_)) => return Err(ResourcesError::SwapLimitWithoutMemoryLimit),
+            (Some(memory), Some(memory_swap)) if memory_swap < memory => {
+                return Err(ResourcesError::InvalidValue(
+                    "memory-swap",
+                    "the limit must be greater than the memory limit".to_string(),
+                ))
+            }
+            _ => {}
+        }
+
+        self.memory = memory;
+        self.memory_swap = memory_swap;
+        Ok(())
+    }
+
+    /// Set the swappiness
+    pub fn set_memory_swappiness(&mut self, swappiness: Option<u64>) -> Result<(), ResourcesError> {
+        match swappiness {
+            Some(swappiness) if swappiness > 100 => Err(ResourcesError::InvalidValue(
+                "memory-swappiness",
+                swappiness.to_string(),
+            )),
+            _ => {
+                self.memory_swappiness = swappiness;
+                Ok(())
+            }
+        }
+    }
+
+    /// Retrieve the relative CPU weight
+    pub fn cpu_shares(&self) -> Option<u64> {
+        self.cpu_shares
+    }
+
+    /// Set the relative CPU weight
+    pub fn set_cpu_shares(&mut self, cpu_shares: Option<u64>) -> Result<(), ResourcesError> {
+        match cpu_shares {
+            Some(cpu_shares) if cpu_shares < 2 => Err(ResourcesError::InvalidValue(
+                "cpu-shares",
+                cpu_shares.to_string(),
+            )),
+            _ => {
+                self.cpu_shares = cpu_shares;
+                Ok(())
+            }
+        }
+    }
+
+    /// Retrieve the number of CPUs worth of time the container can use
+    pub fn cpus(&self) -> Option<f64> {
+        self.cpus
+    }
+
+    /// Retrieve the CPU time the container can use per [`CPU_PERIOD`], in microseconds
+    pub fn cpu_quota(&self) -> Option<u64> {
+        self.cpus.map(|cpus| (cpus * CPU_PERIOD as f64) as u64)
+    }
+
+    /// Set the number of CPUs worth of time the container can use
+    pub fn set_cpus(&mut self, cpus: Option<f64>) -> Result<(), ResourcesError> {
+        match cpus {
+            // The kernel does not accept quotas under a millisecond
+            Some(cpus) if !(cpus.is_finite() && cpus * CPU_PERIOD as f64 >= 1000.) => {
+                Err(ResourcesError::InvalidValue("cpus", cpus.to_string()))
+            }
+            _ => {
+                self.cpus = cpus;
+                Ok(())
+            }
+        }
+    }
+
+    /// Retrieve the CPUs the container is allowed to run on
+    pub fn cpuset_cpus(&self) -> Option<&str> {
+        self.cpuset_cpus.as_deref()
+    }
+
+    /// Set the CPUs the container is allowed to run on
+    pub fn set_cpuset_cpus(&mut self, cpuset_cpus: Option<String>) -> Result<(), ResourcesError> {
+        match cpuset_cpus {
+            Some(ref cpus) if !is_valid_cpuset(cpus) => {
+                Err(ResourcesError::InvalidValue("cpuset-cpus", cpus.clone()))
+            }
+            _ => {
+                self.cpuset_cpus = cpuset_cpus;
+                Ok(())
+            }
+        }
+    }
+
+    /// Retrieve the memory nodes the container is allowed to use
+    pub fn cpuset_mems(&self) -> Option<&str> {
+        self.cpuset_mems.as_deref()
+    }
+
+    /// Set the memory nodes the container is allowed to use
+    pub fn set_cpuset_mems(&mut self, cpuset_mems: Option<String>) -> Result<(), ResourcesError> {
+        match cpuset_mems {
+            Some(ref mems) if !is_valid_cpuset(mems) => {
+                Err(ResourcesError::InvalidValue("cpuset-mems", mems.clone()))
+            }
+            _ => {
+                self.cpuset_mems = cpuset_mems;
+                Ok(())
+            }
+        }
+    }
+
+    /// Retrieve the maximum number of processes and threads in the container
+    pub fn pids_limit(&self) -> Option<u64> {
+        self.pids_limit
+    }
+
+    /// Set the maximum number of processes and threads in the container
+    pub fn set_pids_limit(&mut self, pids_limit: Option<u64>) -> Result<(), ResourcesError> {
+        match pids_limit {
+            Some(0) => Err(ResourcesError::InvalidValue("pids-limit", "0".to_string())),
+            _ => {
+                self.pids_limit = pids_limit;
+                Ok(())
+            }
+        }
+    }
+
+    /// Retrieve the relative block I/O weight
+    pub fn blkio_weight(&self) -> Option<u64> {
+        self.blkio_weight
+    }
+
+    /// Set the relative block I/O weight
+    pub fn set_blkio_weight(&mut self, blkio_weight: Option<u64>) -> Result<(), ResourcesError> {
+        match blkio_weight {
+            Some(weight) if weight < 10 || weight > 1000 => Err(ResourcesError::InvalidValue(
+                "blkio-weight",
+                weight.to_string(),
+            )),
+            _ => {
+                self.blkio_weight = blkio_weight;
+                Ok(())
+            }
+        }
+    }
+
+    /// Retrieve the limits on the bytes read per second from block devices
+    pub fn device_read_bps(&self) -> &[DeviceRate] {
+        &self.device_read_bps
+    }
+
+    /// Set the limits on the bytes read per second from block devices
+    pub fn set_device_read_bps(&mut self, rates: Vec<DeviceRate>) {
+        self.device_read_bps = rates;
+    }
+
+    /// Retrieve the limits on the bytes written per second to block devices
+    pub fn device_write_bps(&self) -> &[DeviceRate] {
+        &self.device_write_bps
+    }
+
+    /// Set the limits on the bytes written per second to block devices
+    pub fn set_device_write_bps(&mut self, rates: Vec<DeviceRate>) {
+        self.device_write_bps = rates;
+    }
+
+    /// Retrieve the limits on the read operations per second on block devices
+    pub fn device_read_iops(&self) -> &[DeviceRate] {
+        &self.device_read_iops
+    }
+
+    /// Set the limits on the read operations per second on block devices
+    pub fn set_device_read_iops(&mut self, rates: Vec<DeviceRate>) {
+        self.device_read_iops = rates;
+    }
+
+    /// Retrieve the limits on the write operations per second on block devices
+    pub fn device_write_iops(&self) -> &[DeviceRate] {
+        &self.device_write_iops
+    }
+
+    /// Set the limits on the write operations per second on block devices
+    pub fn set_device_write_iops(&mut self, rates: Vec<DeviceRate>) {
+        self.device_write_iops = rates;
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use serde_derive::{Deserialize, Serialize};
+
+/// Retrieve the current time, in seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Retrieve the time a process started at, in clock ticks since the boot of the system, from
+/// the 22nd field of its `/proc/<pid>/stat` file
+fn process_start_time(pid: Pid) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The second field is the name of the program, between parentheses, which may contain spaces
+    // and parentheses itself
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Check whether a process exists, even if we are not allowed to signal it
+///
+/// When its start time is known, a process reusing the identifier of an exited one is not
+/// mistaken for it.
+fn is_process_alive(pid: Pid, start_time: Option<u64>) -> bool {
+    let exists = match kill(pid, None) {
+        Ok(()) => true,
+        Err(nix::Error::Sys(Errno::EPERM)) => true,
+        Err(_) => false,
+    };
+    match start_time {
+        Some(start_time) => exists && process_start_time(pid) == Some(start_time),
+        None => exists,
+    }
+}
+
+/// Compute the exit code of a process from its wait status, using 128 + the signal number for
+/// processes killed by a signal like shells do
+pub fn exit_code(status: WaitStatus) -> Option<i32> {
+    match status {
+        WaitStatus::Exited(_, code) => Some(code),
+        WaitStatus::Signaled(_, signal, _) => Some(128 + signal as i32),
+        _ => None,
+    }
+}
+
+/// Enumeration for the stages of the lifecycle of a container
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerStatus {
+    /// The container was created but never ran
+    Created,
+    /// The container is running
+    Running,
+    /// The container ran and exited
+    Exited,
+    /// The container was running when its supervising process disappeared
+    Dead,
+}
+
+impl Default for ContainerStatus {
+    fn default() -> Self {
+        ContainerStatus::Created
+    }
+}
+
+impl std::fmt::Display for ContainerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let status = match self {
+            ContainerStatus::Created => "created",
+            ContainerStatus::Running => "running",
+            ContainerStatus::Exited => "exited",
+            ContainerStatus::Dead => "dead",
+        };
+        f.write_str(status)
+    }
+}
+
+/// Structure describing the runtime state of a container, with times in seconds since the epoch
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ContainerState {
+    #[serde(default)]
+    status: ContainerStatus,
+    #[serde(default)]
+    pid: Option<i32>,
+    #[serde(default)]
+    pid_start_time: Option<u64>,
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    started_at: Option<u64>,
+    #[serde(default)]
+    finished_at: Option<u64>,
+    #[serde(default)]
+    exit_code: Option<i32>,
+    #[serde(default)]
+    oom_killed: bool,
+    #[serde(default)]
+    pids_limit_reached: bool,
+}
+
+impl ContainerState {
+    /// Create the state of a container that was just created
+    pub fn new() -> Self {
+        Self {
+            created_at: Some(now()),
+            ..Default::default()
+        }
+    }
+
+    /// Retrieve the status of the container, which is dead when its process disappeared without
+    /// the exit being recorded
+    pub fn status(&self) -> ContainerStatus {
+        match (self.status, self.pid()) {
+            (ContainerStatus::Running, Some(pid))
+                if !is_process_alive(pid, self.pid_start_time) =>
+            {
+                ContainerStatus::Dead
+            }
+            (status, _) => status,
+        }
+    }
+
+    /// Retrieve the host identifier of the main process of the container, while it is running
+    pub fn pid(&self) -> Option<Pid> {
+        self.pid.map(Pid::from_raw)
+    }
+
+    /// Retrieve the time the container was created at
+    pub fn created_at(&self) -> Option<u64> {
+        self.created_at
+    }
+
+    /// Retrieve the time the container was last started at
+    pub fn started_at(&self) -> Option<u64> {
+        self.started_at
+    }
+
+    /// Retrieve the time the container last exited at
+    pub fn finished_at(&self) -> Option<u64> {
+        self.finished_at
+    }
+
+    /// Retrieve the exit code of the last run of the container
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Check whether the last run of the container was killed for exceeding its memory limit
+    pub fn oom_killed(&self) -> bool {
+        self.oom_killed
+    }
+
+    /// Check whether the last run of the container failed to fork past its pids limit
+    pub fn pids_limit_reached(&self) -> bool {
+        self.pids_limit_reached
+    }
+
+    /// Record that the container started running with a given main process
+    pub fn set_running(&mut self, pid: Pid) {
+        self.status = ContainerStatus::Running;
+        self.pid = Some(pid.as_raw());
+        self.pid_start_time = process_start_time(pid);
+        self.started_at = Some(now());
+        self.finished_at = None;
+        self.exit_code = None;
+        self.oom_killed = false;
+        self.pids_limit_reached = false;
+    }
+
+    /// Record that the main process of the container exited
+    pub fn set_exited(
+        &mut self,
+        exit_code: Option<i32>,
+        oom_killed: bool,
+        pids_limit_reached: bool,
+    ) {
+        self.status = ContainerStatus::Exited;
+        self.pid = None;
+        self.pid_start_time = None;
+        self.finished_at = Some(now());
+        self.exit_code = exit_code;
+        self.oom_killed = oom_killed;
+        self.pids_limit_reached = pids_limit_reached;
+    }
+}
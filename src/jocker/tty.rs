@@ -0,0 +1,330 @@
+use std::io::Write;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+use failure::Fail;
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use nix::sys::stat::Mode;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use nix::sys::uio::IoVec;
+use nix::unistd::{close, dup2, isatty, read, setsid, write};
+
+use super::logs::LineLogger;
+
+/// Delay after which the proxy checks whether the window of the host terminal was resized
+const POLL_TIMEOUT_MS: i32 = 100;
+
+/// Error type for terminal-related errors
+#[derive(Fail, Debug)]
+pub enum TtyError {
+    /// A pseudo-terminal could not be allocated
+    #[fail(display = "cannot allocate a pseudo-terminal: {}", _0)]
+    CannotAllocate(nix::Error),
+
+    /// A pseudo-terminal could not be passed between processes
+    #[fail(display = "cannot transfer the pseudo-terminal: {}", _0)]
+    CannotTransfer(nix::Error),
+
+    /// A terminal could not be configured
+    #[fail(display = "cannot configure the terminal: {}", _0)]
+    CannotConfigure(nix::Error),
+}
+
+/// Whether the window of the host terminal was resized since its size was last propagated
+static WINDOW_RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_window_resize(_: libc::c_int) {
+    WINDOW_RESIZED.store(true, Ordering::SeqCst);
+}
+
+/// Allocate a pseudo-terminal from the devpts instance mounted in the current root, returning
+/// its master, its slave and the path to its slave
+pub fn open_pty() -> Result<(RawFd, RawFd, PathBuf), TtyError> {
+    let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC)
+        .map_err(TtyError::CannotAllocate)?;
+    grantpt(&master).map_err(TtyError::CannotAllocate)?;
+    unlockpt(&master).map_err(TtyError::CannotAllocate)?;
+
+    let slave_path = PathBuf::from(ptsname_r(&master).map_err(TtyError::CannotAllocate)?);
+    let slave = open(&slave_path, OFlag::O_RDWR | OFlag::O_NOCTTY, Mode::empty())
+        .map_err(TtyError::CannotAllocate)?;
+
+    Ok((master.into_raw_fd(), slave, slave_path))
+}
+
+/// Make the current process the leader of a new session controlled by a terminal, which also
+/// becomes its standard input and outputs
+pub fn set_controlling_terminal(slave: RawFd) -> Result<(), TtyError> {
+    setsid().map_err(TtyError::CannotConfigure)?;
+    Errno::result(unsafe { libc::ioctl(slave, libc::TIOCSCTTY, 0) })
+        .map_err(TtyError::CannotConfigure)?;
+
+    for fd in 0..3 {
+        dup2(slave, fd).map_err(TtyError::CannotConfigure)?;
+    }
+    if slave > 2 {
+        close(slave).map_err(TtyError::CannotConfigure)?;
+    }
+    Ok(())
+}
+
+/// Send a file descriptor to another process over a Unix socket
+pub fn send_fd(socket: RawFd, fd: RawFd) -> Result<(), TtyError> {
+    let fds = [fd];
+
+    sendmsg(
+        socket,
+        &[IoVec::from_slice(&[0])],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )
+    .map_err(TtyError::CannotTransfer)?;
+    Ok(())
+}
+
+/// Receive a file descriptor sent by another process with [`send_fd`]
+pub fn receive_fd(socket: RawFd) -> Result<RawFd, TtyError> {
+    let mut buffer = [0];
+    let mut cmsg_buffer = nix::cmsg_space!(RawFd);
+
+    let message = recvmsg(
+        socket,
+        &[IoVec::from_mut_slice(&mut buffer)],
+        Some(&mut cmsg_buffer),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .map_err(TtyError::CannotTransfer)?;
+
+    message
+        .cmsgs()
+        .find_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => fds.first().cloned(),
+            _ => None,
+        })
+        .ok_or(TtyError::CannotTransfer(nix::Error::Sys(Errno::EBADMSG)))
+}
+
+/// Structure switching a terminal to raw mode, and restoring its settings when dropped
+pub struct RawMode {
+    fd: RawFd,
+    termios: Termios,
+}
+
+impl RawMode {
+    /// Switch a terminal to raw mode, so that keys are passed untouched to the container, or
+    /// do nothing if the file descriptor is not a terminal
+    pub fn enable(fd: RawFd) -> Result<Option<Self>, TtyError> {
+        if !isatty(fd).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let termios = tcgetattr(fd).map_err(TtyError::CannotConfigure)?;
+        let mut raw_termios = termios.clone();
+        cfmakeraw(&mut raw_termios);
+        tcsetattr(fd, SetArg::TCSANOW, &raw_termios).map_err(TtyError::CannotConfigure)?;
+
+        Ok(Some(Self { fd, termios }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(self.fd, SetArg::TCSANOW, &self.termios);
+    }
+}
+
+/// Size of a window size once encoded with [`encode_window_size`]
+pub const WINDOW_SIZE_LEN: usize = 8;
+
+/// Retrieve the window size of the host terminal, if there is one
+pub fn window_size() -> Option<libc::winsize> {
+    let from = (0..3).find(|fd| isatty(*fd).unwrap_or(false))?;
+
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(from, libc::TIOCGWINSZ, &mut size) } == 0 {
+        Some(size)
+    } else {
+        None
+    }
+}
+
+/// Give a terminal a window size
+fn set_window_size(to: RawFd, size: &libc::winsize) {
+    unsafe { libc::ioctl(to, libc::TIOCSWINSZ, size) };
+}
+
+/// Encode a window size to be sent to another process
+pub fn encode_window_size(size: &libc::winsize) -> [u8; WINDOW_SIZE_LEN] {
+    let mut encoded = [0; WINDOW_SIZE_LEN];
+    let fields = [size.ws_row, size.ws_col, size.ws_xpixel, size.ws_ypixel];
+    for (i, field) in fields.iter().enumerate() {
+        encoded[2 * i..2 * i + 2].copy_from_slice(&field.to_be_bytes());
+    }
+    encoded
+}
+
+/// Decode a window size encoded with [`encode_window_size`]
+fn decode_window_size(encoded: &[u8; WINDOW_SIZE_LEN]) -> libc::winsize {
+    let field = |i: usize| u16::from_be_bytes([encoded[2 * i], encoded[2 * i + 1]]);
+    libc::winsize {
+        ws_row: field(0),
+        ws_col: field(1),
+        ws_xpixel: field(2),
+        ws_ypixel: field(3),
+    }
+}
+
+/// Start recording the resizes of the host terminal's window, which [`window_resized`] reports
+///
+/// A resize interrupts the blocking calls of the thread receiving the signal.
+pub fn watch_window_resizes() {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_window_resize),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    let _ = unsafe { sigaction(Signal::SIGWINCH, &action) };
+    WINDOW_RESIZED.store(true, Ordering::SeqCst);
+}
+
+/// Stop recording the resizes of the host terminal's window
+pub fn unwatch_window_resizes() {
+    let default_action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    let _ = unsafe { sigaction(Signal::SIGWINCH, &default_action) };
+}
+
+/// Tell whether the window of the host terminal was resized since this was last checked, which
+/// is the case right after [`watch_window_resizes`] is called
+pub fn window_resized() -> bool {
+    WINDOW_RESIZED.swap(false, Ordering::SeqCst)
+}
+
+/// Write a whole buffer to a file descriptor
+fn write_all(fd: RawFd, mut data: &[u8]) -> Result<(), nix::Error> {
+    while !data.is_empty() {
+        match write(fd, data) {
+            Ok(written) => data = &data[written..],
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Proxy a pseudo-terminal to the standard output, copying what is read from an input to it
+/// and propagating the resizes of the host terminal's window
+///
+/// The output is also logged if a logger is given. Window sizes encoded with
+/// [`encode_window_size`] can also be read from another input, for the terminals of clients
+/// attached to a detached container. The proxy ends once every process using the slave of the
+/// pseudo-terminal has closed it, and then closes the master.
+pub fn proxy(
+    master: RawFd,
+    input: Option<RawFd>,
+    window_sizes: Option<RawFd>,
+    mut logger: Option<LineLogger>,
+) -> JoinHandle<()> {
+    watch_window_resizes();
+
+    thread::spawn(move || {
+        let mut input = input;
+        let mut window_sizes = window_sizes;
+        let mut buffer = [0; 4096];
+
+        loop {
+            if window_resized() {
+                if let Some(size) = window_size() {
+                    set_window_size(master, &size);
+                }
+            }
+
+            let mut poll_fds = vec![PollFd::new(master, PollFlags::POLLIN)];
+            if let Some(input) = input {
+                poll_fds.push(PollFd::new(input, PollFlags::POLLIN));
+            }
+            if let Some(window_sizes) = window_sizes {
+                poll_fds.push(PollFd::new(window_sizes, PollFlags::POLLIN));
+            }
+            match poll(&mut poll_fds, POLL_TIMEOUT_MS) {
+                Ok(0) | Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Ok(_) => {}
+                Err(_) => break,
+            }
+
+            let ready = |poll_fd: &PollFd| {
+                poll_fd
+                    .revents()
+                    .map_or(false, |revents| !revents.is_empty())
+            };
+
+            if ready(&poll_fds[0]) {
+                // Reading fails with EIO once the slave is closed by every process
+                let count = match read(master, &mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(count) => count,
+                };
+                let mut stdout = std::io::stdout();
+                let _ = stdout
+                    .write_all(&buffer[..count])
+                    .and_then(|_| stdout.flush());
+                if let Some(logger) = &mut logger {
+                    if logger.write(&buffer[..count]).is_err() {
+                        eprintln!("warning: cannot log the output of the container");
+                    }
+                }
+            }
+
+            let mut poll_fds = poll_fds.iter().skip(1);
+            if input.is_some() && poll_fds.next().map_or(false, ready) {
+                match read(input.unwrap(), &mut buffer) {
+                    Ok(0) | Err(_) => input = None,
+                    Ok(count) => {
+                        let _ = write_all(master, &buffer[..count]);
+                    }
+                }
+            }
+
+            // Window sizes are written at once, and are thus read at once from a pipe
+            if window_sizes.is_some() && poll_fds.next().map_or(false, ready) {
+                let mut encoded = [0; WINDOW_SIZE_LEN];
+                match read(window_sizes.unwrap(), &mut encoded) {
+                    Ok(WINDOW_SIZE_LEN) => set_window_size(master, &decode_window_size(&encoded)),
+                    Err(nix::Error::Sys(Errno::EAGAIN)) => {}
+                    Ok(0) | Err(_) => window_sizes = None,
+                    Ok(_) => {}
+                }
+            }
+        }
+
+        if let Some(logger) = &mut logger {
+            let _ = logger.flush();
+        }
+        let _ = close(master);
+        unwatch_window_resizes();
+    })
+}
+
+/// Bind-mount the slave of a pseudo-terminal on `/dev/console` in the current root
+pub fn mount_console(slave_path: &Path) -> Result<(), nix::Error> {
+    let console = Path::new("/dev/console");
+    if !console.exists() {
+        std::fs::File::create(console).map_err(|_| nix::Error::last())?;
+    }
+
+    nix::mount::mount::<Path, Path, Path, Path>(
+        Some(slave_path),
+        console,
+        None,
+        nix::mount::MsFlags::MS_BIND,
+        None,
+    )
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::Fail;
+use nix::unistd::{Gid, Uid};
+
+/// Error type for user-related errors
+#[derive(Fail, Debug)]
+pub enum UserError {
+    /// The user does not exist in the container's user database
+    #[fail(display = "unknown user {}", _0)]
+    UnknownUser(String),
+
+    /// The group does not exist in the container's group database
+    #[fail(display = "unknown group {}", _0)]
+    UnknownGroup(String),
+}
+
+/// Structure describing an entry of a `passwd` database
+#[derive(Clone, Debug)]
+pub struct PasswdEntry {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: PathBuf,
+}
+
+impl PasswdEntry {
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<_> = line.split(':').collect();
+        if fields.len() < 6 {
+            return None;
+        }
+
+        Some(Self {
+            name: fields[0].to_string(),
+            uid: fields[2].parse().ok()?,
+            gid: fields[3].parse().ok()?,
+            home: PathBuf::from(fields[5]),
+        })
+    }
+
+    /// Retrieve the name of the user
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Retrieve the identifier of the user
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+}
+
+/// Structure describing an entry of a `group` database
+#[derive(Clone, Debug)]
+struct GroupEntry {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+impl GroupEntry {
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<_> = line.split(':').collect();
+        if fields.len() < 4 {
+            return None;
+        }
+
+        Some(Self {
+            name: fields[0].to_string(),
+            gid: fields[2].parse().ok()?,
+            members: fields[3]
+                .split(',')
+                .filter(|member| !member.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    }
+}
+
+/// Read the entries of a `passwd` database, ignoring malformed lines
+pub fn passwd_entries(path: &Path) -> Vec<PasswdEntry> {
+    fs::read_to_string(path)
+        .map(|content| content.lines().filter_map(PasswdEntry::parse).collect())
+        .unwrap_or_default()
+}
+
+/// Read the entries of a `group` database, ignoring malformed lines
+fn group_entries(path: &Path) -> Vec<GroupEntry> {
+    fs::read_to_string(path)
+        .map(|content| content.lines().filter_map(GroupEntry::parse).collect())
+        .unwrap_or_default()
+}
+
+/// Structure describing the identity a process should run as
+#[derive(Clone, Debug)]
+pub struct User {
+    uid: Uid,
+    gid: Gid,
+    groups: Vec<Gid>,
+    home: PathBuf,
+}
+
+impl User {
+    /// Resolve a `USER[:GROUP]` specification, using the user databases of the given root
+    ///
+    /// Users and groups can be given either by name or by identifier. Numeric identifiers that
+    /// are not in the databases are allowed, like in Docker.
+    pub fn resolve(spec: &str, root: &Path) -> Result<Self, UserError> {
+        let mut pieces = spec.splitn(2, ':');
+        let user = pieces.next().unwrap_or("");
+        let group = pieces.next();
+
+        let passwd = passwd_entries(&root.join("etc").join("passwd"));
+        let groups = group_entries(&root.join("etc").join("group"));
+
+        let entry = passwd
+            .into_iter()
+            .find(|entry| entry.name == user || entry.uid.to_string() == user);
+        let (uid, primary_gid, name, home) = match (entry, user.parse::<u32>()) {
+            (Some(entry), _) => (entry.uid, entry.gid, Some(entry.name), entry.home),
+            (None, Ok(uid)) => (uid, 0, None, PathBuf::from("/")),
+            (None, Err(_)) => return Err(UserError::UnknownUser(user.to_string())),
+        };
+
+        let gid = match group {
+            Some(group) => groups
+                .iter()
+                .find(|entry| entry.name == group)
+                .map(|entry| entry.gid)
+                .or_else(|| group.parse().ok())
+                .ok_or_else(|| UserError::UnknownGroup(group.to_string()))?,
+            None => primary_gid,
+        };
+
+        // Supplementary groups are only looked up when the user is not given an explicit group
+        let mut supplementary_groups = vec![gid];
+        if let (Some(name), None) = (&name, group) {
+            supplementary_groups.extend(
+                groups
+                    .iter()
+                    .filter(|entry| entry.members.contains(name) && entry.gid != gid)
+                    .map(|entry| entry.gid),
+            );
+        }
+
+        Ok(Self {
+            uid: Uid::from_raw(uid),
+            gid: Gid::from_raw(gid),
+            groups: supplementary_groups
+                .into_iter()
+                .map(Gid::from_raw)
+                .collect(),
+            home,
+        })
+    }
+
+    /// Retrieve the user identifier
+    pub fn uid(&self) -> Uid {
+        self.uid
+    }
+
+    /// Retrieve the primary group identifier
+    pub fn gid(&self) -> Gid {
+        self.gid
+    }
+
+    /// Retrieve all the groups of the user, including the primary group
+    pub fn groups(&self) -> &[Gid] {
+        &self.groups
+    }
+
+    /// Retrieve the home directory of the user
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use failure::Fail;
+use nix::unistd::{getegid, geteuid, Pid};
+
+use super::user::passwd_entries;
+
+/// Error type for user namespace-related errors
+#[derive(Fail, Debug)]
+pub enum UserNamespaceError {
+    /// An identifier mapping could not be written for the container process
+    #[fail(display = "cannot write {}: {}", _0, _1)]
+    CannotWriteMapping(String, std::io::Error),
+
+    /// A mapping helper could not be executed
+    #[fail(display = "cannot execute {}: {}", _0, _1)]
+    CannotRunHelper(String, std::io::Error),
+
+    /// A mapping helper exited with an error
+    #[fail(display = "{} failed", _0)]
+    HelperFailed(String),
+}
+
+/// Structure describing a range of identifiers mapped from the host into a user namespace
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+struct IdMapping {
+    container_id: u32,
+    host_id: u32,
+    size: u32,
+}
+
+impl IdMapping {
+    fn new(container_id: u32, host_id: u32, size: u32) -> Self {
+        Self {
+            container_id,
+            host_id,
+            size,
+        }
+    }
+}
+
+/// Check whether containers have to be run without privileges on the host
+pub fn is_rootless() -> bool {
+    !geteuid().is_root()
+}
+
+/// Retrieve the name of the user with the given identifier on the host
+fn host_user_name(uid: u32) -> Option<String> {
+    passwd_entries(Path::new("/etc/passwd"))
+        .into_iter()
+        .find(|entry| entry.uid() == uid)
+        .map(|entry| entry.name().to_string())
+}
+
+/// Find the first subordinate identifier range delegated to a user in `/etc/subuid` or `/etc/subgid`
+fn subordinate_range(path: &Path, user_name: Option<&str>, id: u32) -> Option<(u32, u32)> {
+    let content = fs::read_to_string(path).ok()?;
+    let id = id.to_string();
+
+    content.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let owner = fields.next()?;
+        if owner != id && Some(owner) != user_name {
+            return None;
+        }
+        let start = fields.next()?.parse().ok()?;
+        let count = fields.next()?.parse().ok()?;
+        Some((start, count))
+    })
+}
+
+/// Compute the mappings for an identifier, adding its subordinate range if there is one
+fn mappings_for(id: u32, subordinate_range: Option<(u32, u32)>) -> Vec<IdMapping> {
+    let mut mappings = vec![IdMapping::new(0, id, 1)];
+
+    if let Some((start, count)) = subordinate_range {
+        mappings.push(IdMapping::new(1, start, count));
+    }
+    mappings
+}
+
+/// Write mappings through a setuid helper such as `newuidmap`, which may map subordinate ranges
+fn write_mappings_with_helper(
+    helper: &str,
+    pid: Pid,
+    mappings: &[IdMapping],
+) -> Result<(), UserNamespaceError> {
+    let mut command = Command::new(helper);
+    command.arg(pid.to_string());
+    for mapping in mappings {
+        command.args(&[
+            mapping.container_id.to_string(),
+            mapping.host_id.to_string(),
+            mapping.size.to_string(),
+        ]);
+    }
+
+    let status = command
+        .status()
+        .map_err(|e| UserNamespaceError::CannotRunHelper(helper.to_string(), e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(UserNamespaceError::HelperFailed(helper.to_string()))
+    }
+}
+
+/// Write a mapping file of a process directly, which only allows mapping our own identifier
+fn write_mapping_file(pid: Pid, file: &str, content: &str) -> Result<(), UserNamespaceError> {
+    let path = format!("/proc/{}/{}", pid, file);
+
+    fs::write(&path, content).map_err(|e| UserNamespaceError::CannotWriteMapping(path, e))
+}
+
+/// Map the current user to root inside the user namespace of a process, along with the
+/// subordinate identifiers delegated to the user in `/etc/subuid` and `/etc/subgid`
+pub fn setup_id_mappings(pid: Pid) -> Result<(), UserNamespaceError> {
+    let uid = geteuid().as_raw();
+    let gid = getegid().as_raw();
+    let user_name = host_user_name(uid);
+
+    let subuid_range = subordinate_range(Path::new("/etc/subuid"), user_name.as_deref(), uid);
+    let subgid_range = subordinate_range(Path::new("/etc/subgid"), user_name.as_deref(), uid);
+
+    if subuid_range.is_some() && subgid_range.is_some() {
+        let uid_mappings = mappings_for(uid, subuid_range);
+        let gid_mappings = mappings_for(gid, subgid_range);
+
+        if write_mappings_with_helper("newuidmap", pid, &uid_mappings).is_ok() {
+            return write_mappings_with_helper("newgidmap", pid, &gid_mappings);
+        }
+    }
+
+    // Without helpers, the kernel only lets us map our own identifiers, and forbids
+    // setgroups(2) so that nobody can drop supplementary groups to bypass permissions
+    write_mapping_file(pid, "uid_map", &format!("0 {} 1", uid))?;
+    write_mapping_file(pid, "setgroups", "deny")?;
+    write_mapping_file(pid, "gid_map", &format!("0 {} 1", gid))
+}
@@ -0,0 +1,365 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use failure::Fail;
+use nix::errno::Errno;
+use nix::mount::{mount, MsFlags};
+use serde_derive::{Deserialize, Serialize};
+
+/// Maximum number of symbolic links followed when resolving a path in a container
+const MAX_SYMLINKS: u32 = 40;
+
+/// Error type for volume-related errors
+#[derive(Fail, Debug)]
+pub enum VolumeError {
+    /// A volume was given with an invalid specification
+    #[fail(display = "invalid volume specification {}", _0)]
+    InvalidSpecification(String),
+
+    /// The source of a bind mount is neither an absolute host path nor a valid volume name
+    #[fail(display = "{} is neither an absolute path nor a volume name", _0)]
+    RelativeSourcePath(String),
+
+    /// The host path of a bind mount does not exist
+    #[fail(display = "host path {} does not exist", _0)]
+    SourceNotFound(String),
+
+    /// The container path of a volume is not absolute
+    #[fail(display = "container path {} is not absolute", _0)]
+    RelativeTargetPath(String),
+
+    /// The container path of a volume is outside of the container's root filesystem
+    #[fail(display = "container path {} escapes the root filesystem", _0)]
+    TargetEscapesRootfs(String),
+
+    /// The container path of a volume could not be resolved
+    #[fail(display = "cannot resolve container path {}: {}", _0, _1)]
+    CannotResolveTarget(String, std::io::Error),
+
+    /// The mount point of a volume could not be created
+    #[fail(display = "cannot create mount point {}: {}", _0, _1)]
+    CannotCreateMountPoint(String, std::io::Error),
+
+    /// A volume could not be mounted
+    #[fail(display = "cannot mount {}: {}", _0, _1)]
+    CannotMount(String, nix::Error),
+
+    /// A volume was created with an invalid name
+    #[fail(display = "invalid volume name {}", _0)]
+    InvalidName(String),
+
+    /// A volume was created with the name of an existing volume
+    #[fail(display = "volume {} already exists", _0)]
+    AlreadyExists(String),
+
+    /// A volume could not be created
+    #[fail(display = "unable to create volume: {}", _0)]
+    CannotCreateVolume(std::io::Error),
+
+    /// A volume could not be removed
+    #[fail(display = "unable to remove volume: {}", _0)]
+    CannotRemoveVolume(std::io::Error),
+}
+
+/// Normalize an absolute path lexically, rejecting paths that would go above the root
+fn normalize_absolute_path(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::from("/");
+
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(name) => normalized.push(name),
+            Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Resolve an absolute path inside a root directory, following symbolic links as if the root
+/// directory was `/`, so that the resulting path can never be outside of it
+fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf, std::io::Error> {
+    let mut resolved = PathBuf::new();
+    let mut remaining: Vec<PathBuf> = vec![path.to_path_buf()];
+    let mut followed_symlinks = 0;
+
+    while let Some(path) = remaining.pop() {
+        let mut components = path.components();
+        let component = match components.next() {
+            Some(component) => component,
+            None => continue,
+        };
+        let rest = components.as_path();
+        if rest != Path::new("") {
+            remaining.push(rest.to_path_buf());
+        }
+
+        match component {
+            Component::RootDir => resolved = PathBuf::new(),
+            Component::CurDir | Component::Prefix(_) => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                let candidate = resolved.join(name);
+                match fs::symlink_metadata(root.join(&candidate)) {
+                    Ok(ref metadata) if metadata.file_type().is_symlink() => {
+                        followed_symlinks += 1;
+                        if followed_symlinks > MAX_SYMLINKS {
+                            return Err(std::io::Error::from_raw_os_error(Errno::ELOOP as i32));
+                        }
+                        // Absolute targets restart from the root thanks to their root component
+                        remaining.push(fs::read_link(root.join(&candidate))?);
+                    }
+                    _ => resolved = candidate,
+                }
+            }
+        }
+    }
+
+    Ok(root.join(resolved))
+}
+
+/// Check whether a string can be used as the name of a volume
+fn is_valid_volume_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Enumeration for the sources of the paths mounted in containers
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MountSource {
+    /// A directory or file of the host
+    Host(PathBuf),
+    /// A named volume managed by jocker
+    Volume(String),
+}
+
+/// Structure describing a host directory or file mounted in a container
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct BindMount {
+    source: MountSource,
+    target: PathBuf,
+    #[serde(default)]
+    read_only: bool,
+}
+
+impl FromStr for BindMount {
+    type Err = VolumeError;
+
+    /// Parse a bind mount given as `/host/path:/container/path[:ro|:rw]`, or as
+    /// `name:/container/path[:ro|:rw]` to mount a named volume
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pieces: Vec<_> = s.split(':').collect();
+        let (source, target, read_only) = match pieces.as_slice() {
+            [source, target] => (source, target, false),
+            [source, target, "ro"] => (source, target, true),
+            [source, target, "rw"] => (source, target, false),
+            _ => return Err(VolumeError::InvalidSpecification(s.to_string())),
+        };
+
+        let source = if source.starts_with('/') {
+            MountSource::Host(PathBuf::from(source))
+        } else if is_valid_volume_name(source) {
+            MountSource::Volume(source.to_string())
+        } else {
+            return Err(VolumeError::RelativeSourcePath(source.to_string()));
+        };
+
+        let target = Path::new(target);
+        if !target.is_absolute() {
+            return Err(VolumeError::RelativeTargetPath(
+                target.display().to_string(),
+            ));
+        }
+        let target = normalize_absolute_path(target)
+            .ok_or_else(|| VolumeError::TargetEscapesRootfs(target.display().to_string()))?;
+
+        Ok(Self {
+            source,
+            target,
+            read_only,
+        })
+    }
+}
+
+impl BindMount {
+    /// Retrieve the source of the mounted directory or file
+    pub fn source(&self) -> &MountSource {
+        &self.source
+    }
+
+    /// Check whether the mount refers to a given named volume
+    pub fn uses_volume(&self, name: &str) -> bool {
+        self.source == MountSource::Volume(name.to_string())
+    }
+
+    /// Retrieve the path the directory or file is mounted at in the container
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Check whether the mount is read-only
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Retrieve the path to mount on the host, creating named volumes that do not exist yet
+    fn source_path(&self, volume_store: &VolumeStore) -> Result<PathBuf, VolumeError> {
+        match &self.source {
+            MountSource::Host(path) if path.exists() => Ok(path.clone()),
+            MountSource::Host(path) => Err(VolumeError::SourceNotFound(path.display().to_string())),
+            MountSource::Volume(name) => match volume_store.get_volume(name) {
+                Some(volume) => Ok(volume.path().to_path_buf()),
+                None => Ok(volume_store.create_volume(name)?.path().to_path_buf()),
+            },
+        }
+    }
+
+    /// Mount the directory or file in the given root filesystem
+    pub fn mount_in(&self, rootfs: &Path, volume_store: &VolumeStore) -> Result<(), VolumeError> {
+        let source = self.source_path(volume_store)?;
+
+        let target_name = self.target.display().to_string();
+        let target = resolve_in_root(rootfs, &self.target)
+            .map_err(|e| VolumeError::CannotResolveTarget(target_name.clone(), e))?;
+
+        // Create a mount point of the same kind as the mounted path
+        let mount_point_result = if source.is_dir() {
+            fs::create_dir_all(&target)
+        } else {
+            target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| {
+                    fs::OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .open(&target)
+                        .map(|_| ())
+                })
+        };
+        mount_point_result
+            .map_err(|e| VolumeError::CannotCreateMountPoint(target_name.clone(), e))?;
+
+        mount::<Path, Path, Path, Path>(
+            Some(&source),
+            &target,
+            None,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None,
+        )
+        .map_err(|e| VolumeError::CannotMount(target_name.clone(), e))?;
+
+        // Bind mounts ignore the read-only flag on creation, it has to be set by remounting
+        if self.read_only {
+            mount::<Path, Path, Path, Path>(
+                None,
+                &target,
+                None,
+                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_REC,
+                None,
+            )
+            .map_err(|e| VolumeError::CannotMount(target_name, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Structure representing a handle over a jocker volume stored at a given path
+#[derive(Debug)]
+pub struct Volume {
+    path: PathBuf,
+}
+
+impl Volume {
+    fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Retrieve the name of the volume
+    pub fn name(&self) -> &Path {
+        Path::new(self.path.file_name().expect("invalid volume path"))
+    }
+
+    /// Retrieve the path to the volume, which is the directory mounted in containers
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Structure representing a handle over a directory storing jocker volumes
+#[derive(Debug)]
+pub struct VolumeStore<'a> {
+    volumes_dir: &'a Path,
+}
+
+impl<'a> VolumeStore<'a> {
+    /// Create a [`VolumeStore`] from a path
+    pub fn from_directory(volumes_dir: &'a Path) -> Self {
+        Self { volumes_dir }
+    }
+
+    /// Retrieve the path to the root directory for this store
+    pub fn path(&self) -> &Path {
+        &self.volumes_dir
+    }
+
+    /// Obtain an iterator over the volumes available in this store, which has none until the
+    /// first one is created
+    pub fn volumes(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Volume, std::io::Error>>, std::io::Error> {
+        let entries = match fs::read_dir(self.volumes_dir) {
+            Ok(entries) => Some(entries),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(entries
+            .into_iter()
+            .flatten()
+            .map(|e| e.map(|entry| Volume::new(entry.path()))))
+    }
+
+    /// Create an empty volume with a given name
+    pub fn create_volume(&self, name: &str) -> Result<Volume, VolumeError> {
+        if !is_valid_volume_name(name) {
+            return Err(VolumeError::InvalidName(name.to_string()));
+        }
+
+        let path = self.volumes_dir.join(name);
+        if path.exists() {
+            return Err(VolumeError::AlreadyExists(name.to_string()));
+        }
+        fs::create_dir_all(&path).map_err(VolumeError::CannotCreateVolume)?;
+
+        Ok(Volume::new(path))
+    }
+
+    /// Get a handle over a specific volume in this store
+    pub fn get_volume(&self, name: &str) -> Option<Volume> {
+        let path = self.volumes_dir.join(name);
+
+        if is_valid_volume_name(name) && path.exists() {
+            Some(Volume::new(path))
+        } else {
+            None
+        }
+    }
+
+    /// Remove a volume and its content from the store
+    pub fn remove_volume(&self, volume: Volume) -> Result<(), VolumeError> {
+        fs::remove_dir_all(volume.path()).map_err(VolumeError::CannotRemoveVolume)
+    }
+}
@@ -7,15 +7,203 @@ use clap::{crate_name, App, AppSettings, Arg, SubCommand};
 mod commands;
 mod jocker;
 
+/// Arguments configuring the processes of a container, shared by `run`, `container start` and
+/// `exec`
+fn process_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("env")
+            .help("set an environment variable, given as KEY=VALUE or KEY to use the current value")
+            .short("e")
+            .long("env")
+            .takes_value(true)
+            .number_of_values(1)
+            .multiple(true),
+        Arg::with_name("env-file")
+            .help("read environment variables from a file, one per line")
+            .long("env-file")
+            .takes_value(true)
+            .number_of_values(1)
+            .multiple(true),
+        Arg::with_name("workdir")
+            .help("the working directory of the command in the container")
+            .short("w")
+            .long("workdir")
+            .takes_value(true),
+        Arg::with_name("user")
+            .help("the user to run the command as, given as USER[:GROUP] names or identifiers")
+            .short("u")
+            .long("user")
+            .takes_value(true),
+    ]
+}
+
+/// Arguments configuring a container, shared by `run` and `container start`
+fn container_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = process_args();
+    args.push(
+        Arg::with_name("volume")
+            .help(
+                "mount a host path or a named volume in the container, \
+                 given as /host/path:/container/path[:ro] or name:/container/path[:ro]",
+            )
+            .short("v")
+            .long("volume")
+            .takes_value(true)
+            .number_of_values(1)
+            .multiple(true),
+    );
+    args
+}
+
+/// Arguments limiting the resources of a container
+fn resource_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("memory")
+            .help("the memory limit, in bytes or with a unit (b, k, m or g)")
+            .short("m")
+            .long("memory")
+            .takes_value(true),
+        Arg::with_name("memory-swap")
+            .help("the memory and swap limit, or -1 for unlimited swap")
+            .long("memory-swap")
+            .takes_value(true)
+            .requires("memory"),
+        Arg::with_name("memory-swappiness")
+            .help("the tendency of the kernel to swap the memory of the container, from 0 to 100")
+            .long("memory-swappiness")
+            .takes_value(true),
+        Arg::with_name("cpu-shares")
+            .help("the relative weight of the container when CPUs are contended")
+            .short("c")
+            .long("cpu-shares")
+            .takes_value(true),
+        Arg::with_name("cpus")
+            .help("the number of CPUs worth of time the container can use, such as 1.5")
+            .long("cpus")
+            .takes_value(true),
+        Arg::with_name("cpuset-cpus")
+            .help("the CPUs the container can run on, such as 0-3,6")
+            .long("cpuset-cpus")
+            .takes_value(true),
+        Arg::with_name("cpuset-mems")
+            .help("the memory nodes the container can use, such as 0-3,6")
+            .long("cpuset-mems")
+            .takes_value(true),
+        Arg::with_name("pids-limit")
+            .help("the maximum number of processes in the container, or -1 for unlimited")
+            .long("pids-limit")
+            .takes_value(true)
+            .allow_hyphen_values(true),
+        Arg::with_name("blkio-weight")
+            .help("the relative weight of the container when block devices are contended, from 10 to 1000")
+            .long("blkio-weight")
+            .takes_value(true),
+        Arg::with_name("device-read-bps")
+            .help("limit the bytes read per second from a device, given as /dev/path:rate")
+            .long("device-read-bps")
+            .takes_value(true)
+            .number_of_values(1)
+            .multiple(true),
+        Arg::with_name("device-write-bps")
+            .help("limit the bytes written per second to a device, given as /dev/path:rate")
+            .long("device-write-bps")
+            .takes_value(true)
+            .number_of_values(1)
+            .multiple(true),
+        Arg::with_name("device-read-iops")
+            .help("limit the read operations per second on a device, given as /dev/path:rate")
+            .long("device-read-iops")
+            .takes_value(true)
+            .number_of_values(1)
+            .multiple(true),
+        Arg::with_name("device-write-iops")
+            .help("limit the write operations per second on a device, given as /dev/path:rate")
+            .long("device-write-iops")
+            .takes_value(true)
+            .number_of_values(1)
+            .multiple(true),
+    ]
+}
+
 fn main() {
     let app = App::new(crate_name!())
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .setting(AppSettings::VersionlessSubcommands)
         .setting(AppSettings::ColoredHelp)
+        .subcommand(
+            SubCommand::with_name("attach")
+                .about("Attach to the standard streams of a detached container")
+                .arg(
+                    Arg::with_name("detach-keys")
+                        .help("the key sequence detaching from the container, such as ctrl-p,ctrl-q")
+                        .long("detach-keys")
+                        .takes_value(true)
+                        .default_value(jocker::attach::DEFAULT_DETACH_KEYS),
+                )
+                .arg(
+                    Arg::with_name("CONTAINER")
+                        .help("the container to attach to")
+                        .required(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("container")
                 .about("Manage existing containers")
                 .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("kill")
+                        .about("send a signal to running containers")
+                        .arg(
+                            Arg::with_name("signal")
+                                .help("the signal to send, by name or number")
+                                .short("s")
+                                .long("signal")
+                                .takes_value(true)
+                                .default_value("KILL"),
+                        )
+                        .arg(
+                            Arg::with_name("CONTAINER")
+                                .help("the containers to send the signal to")
+                                .required(true)
+                                .multiple(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("logs")
+                        .about("show the output of a container")
+                        .arg(
+                            Arg::with_name("follow")
+                                .help("keep showing the output while the container runs")
+                                .short("f")
+                                .long("follow"),
+                        )
+                        .arg(
+                            Arg::with_name("tail")
+                                .help("only show the given number of lines from the end")
+                                .long("tail")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("since")
+                                .help(
+                                    "only show the output since a timestamp (such as \
+                                     2019-08-20T10:30:00Z or 1566297000) or a duration (such as 10m)",
+                                )
+                                .long("since")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("timestamps")
+                                .help("show the time each line was written at")
+                                .short("t")
+                                .long("timestamps"),
+                        )
+                        .arg(
+                            Arg::with_name("CONTAINER")
+                                .help("the container to show the output of")
+                                .required(true),
+                        ),
+                )
                 .subcommand(
                     SubCommand::with_name("ls")
                         .about("list existing containers")
@@ -36,27 +224,86 @@ fn main() {
                                 .multiple(true),
                         ),
                 )
+                .subcommand(
+                    SubCommand::with_name("stop")
+                        .about("stop running containers")
+                        .arg(
+                            Arg::with_name("time")
+                                .help("the number of seconds to wait before killing the containers")
+                                .short("t")
+                                .long("time")
+                                .takes_value(true)
+                                .default_value("10"),
+                        )
+                        .arg(
+                            Arg::with_name("CONTAINER")
+                                .help("the containers to stop")
+                                .required(true)
+                                .multiple(true),
+                        ),
+                )
                 .subcommand(
                     SubCommand::with_name("start")
                         .about("run a command in an existing stopped container")
+                        .setting(AppSettings::TrailingVarArg)
+                        .setting(AppSettings::AllowLeadingHyphen)
                         .arg(
                             Arg::with_name("CONTAINER")
                                 .help("the container to run")
                                 .required(true),
                         )
                         .arg(
-                            Arg::with_name("COMMAND")
-                                .help("the command to run in a container")
-                                .required(true),
+                            Arg::with_name("shell")
+                                .help("run the command line through /bin/sh -c")
+                                .long("shell"),
                         )
+                        .args(&container_args())
                         .arg(
-                            Arg::with_name("ARG")
-                                .help("the arguments to pass to the command")
-                                .required(false)
+                            // A single positional, so that options after the program are passed to it
+                            Arg::with_name("COMMAND")
+                                .help(
+                                    "the command to run in a container, followed by its arguments \
+                                     (defaults to the command of the image)",
+                                )
                                 .multiple(true),
                         ),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about("Run a command in a running container")
+                .setting(AppSettings::TrailingVarArg)
+                .setting(AppSettings::AllowLeadingHyphen)
+                .arg(
+                    Arg::with_name("interactive")
+                        .help("pass the standard input to the command")
+                        .short("i")
+                        .long("interactive"),
+                )
+                .arg(
+                    Arg::with_name("tty")
+                        .help("allocate a pseudo-terminal for the command")
+                        .short("t")
+                        .long("tty"),
+                )
+                .arg(
+                    Arg::with_name("shell")
+                        .help("run the command line through /bin/sh -c")
+                        .long("shell"),
+                )
+                .args(&process_args())
+                .arg(
+                    Arg::with_name("CONTAINER")
+                        .help("the container to run the command in")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("COMMAND")
+                        .help("the command to run in the container, followed by its arguments")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("image")
                 .about("Manage images")
@@ -71,15 +318,66 @@ fn main() {
                                 .takes_value(true)
                                 .required(false),
                         )
+                        .arg(
+                            Arg::with_name("keep-intermediates")
+                                .help("keep the intermediate containers and images of the build")
+                                .long("keep-intermediates"),
+                        )
+                        .arg(
+                            Arg::with_name("pids-limit")
+                                .help(
+                                    "the maximum number of processes in each build step, \
+                                     or -1 for unlimited",
+                                )
+                                .long("pids-limit")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
                         .arg(
                             Arg::with_name("PATH")
                                 .help("the path to the directory containing the build files")
                                 .required(true),
                         ),
                 )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("export an image")
+                        .arg(
+                            Arg::with_name("format")
+                                .help("the format of the exported image")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["oci"])
+                                .default_value("oci"),
+                        )
+                        .arg(
+                            Arg::with_name("IMAGE")
+                                .help("the image to export")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("OUT")
+                                .help(
+                                    "the directory to write the image layout to, \
+                                     or a tarball if it ends with .tar",
+                                )
+                                .required(true),
+                        ),
+                )
                 .subcommand(
                     SubCommand::with_name("import")
-                        .about("import an image from a tarball")
+                        .about("import an image from a tarball, an image layout or a docker archive")
+                        .arg(
+                            Arg::with_name("format")
+                                .help(
+                                    "the format of the image, a root filesystem tarball, \
+                                     an image layout directory or tarball, or an archive \
+                                     created by docker save, detected if not given",
+                                )
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["tar", "oci", "docker"]),
+                        )
                         .arg(
                             Arg::with_name("NAME")
                                 .help("the name to give to the image")
@@ -87,7 +385,7 @@ fn main() {
                         )
                         .arg(
                             Arg::with_name("PATH")
-                                .help("the path to the tarball to import")
+                                .help("the path to the image to import")
                                 .required(true),
                         ),
                 )
@@ -112,9 +410,87 @@ fn main() {
                         ),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("volume")
+                .about("Manage volumes")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("create a new volume")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("the name to give to the volume")
+                                .required(false),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("inspect")
+                        .about("display information about volumes")
+                        .arg(
+                            Arg::with_name("VOLUME")
+                                .help("the volumes to inspect")
+                                .required(true)
+                                .multiple(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("ls")
+                        .about("list existing volumes")
+                        .arg(
+                            Arg::with_name("quiet")
+                                .help("only list volume names")
+                                .short("q")
+                                .long("quiet"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rm")
+                        .about("remove existing volumes")
+                        .arg(
+                            Arg::with_name("VOLUME")
+                                .help("the volumes to remove")
+                                .required(true)
+                                .multiple(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            // Mode of the init process of containers run with --init
+            SubCommand::with_name("init")
+                .setting(AppSettings::Hidden)
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("uid")
+                        .help("the user ID to run the command as")
+                        .long("uid")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("gid")
+                        .help("the group ID to run the command as")
+                        .long("gid")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("groups")
+                        .help("the comma-separated supplementary group IDs of the command")
+                        .long("groups")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("COMMAND")
+                        .help("the path to the program to run, followed by its arguments")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("run")
                 .about("Create and run containers")
+                .setting(AppSettings::TrailingVarArg)
+                .setting(AppSettings::AllowLeadingHyphen)
                 .arg(
                     Arg::with_name("name")
                         .help("the name to use for this container")
@@ -122,20 +498,75 @@ fn main() {
                         .takes_value(true)
                         .required(false),
                 )
+                .arg(
+                    Arg::with_name("detach")
+                        .help("run the container in the background and print its ID")
+                        .short("d")
+                        .long("detach"),
+                )
+                .arg(
+                    Arg::with_name("init")
+                        .help("run an init process forwarding signals and reaping zombies as PID 1")
+                        .long("init"),
+                )
+                .arg(
+                    Arg::with_name("interactive")
+                        .help("pass the standard input to the pseudo-terminal of the container")
+                        .short("i")
+                        .long("interactive"),
+                )
+                .arg(
+                    Arg::with_name("rm")
+                        .help("remove the container once it exits")
+                        .long("rm"),
+                )
+                .arg(
+                    Arg::with_name("tty")
+                        .help("allocate a pseudo-terminal for the container")
+                        .short("t")
+                        .long("tty"),
+                )
+                .arg(
+                    Arg::with_name("network")
+                        .help(
+                            "the networking mode to use for this container \
+                             (defaults to bridge, or host for unprivileged users)",
+                        )
+                        .long("network")
+                        .takes_value(true)
+                        .possible_values(&["bridge", "host", "none"]),
+                )
+                .arg(
+                    Arg::with_name("shell")
+                        .help("run the command line through /bin/sh -c")
+                        .long("shell"),
+                )
+                .arg(
+                    Arg::with_name("log-max-size")
+                        .help("the size above which the log is rotated (defaults to 10m)")
+                        .long("log-max-size")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("log-max-files")
+                        .help("the maximum number of log files to keep (defaults to 5)")
+                        .long("log-max-files")
+                        .takes_value(true),
+                )
+                .args(&container_args())
+                .args(&resource_args())
                 .arg(
                     Arg::with_name("IMAGE")
                         .help("the image to use as base for the container")
                         .required(true),
                 )
                 .arg(
+                    // A single positional, so that options after the program are passed to it
                     Arg::with_name("COMMAND")
-                        .help("the command to run in a container")
-                        .required(true),
-                )
-                .arg(
-                    Arg::with_name("ARG")
-                        .help("the arguments to pass to the command")
-                        .required(false)
+                        .help(
+                            "the command to run in a container, followed by its arguments \
+                             (defaults to the command of the image)",
+                        )
                         .multiple(true),
                 ),
         );
@@ -149,20 +580,34 @@ fn main() {
     );
 
     let result = match matches.subcommand() {
+        ("attach", Some(matches)) => commands::attach(&config, matches),
         ("container", Some(matches)) => match matches.subcommand() {
+            ("kill", Some(matches)) => commands::containers::kill(&config, matches),
+            ("logs", Some(matches)) => commands::containers::logs(&config, matches),
             ("ls", Some(matches)) => commands::containers::list(&config, matches),
             ("rm", Some(matches)) => commands::containers::remove(&config, matches),
             ("start", Some(matches)) => commands::containers::start(&config, matches),
+            ("stop", Some(matches)) => commands::containers::stop(&config, matches),
             _ => unimplemented!(),
         },
         ("image", Some(matches)) => match matches.subcommand() {
             ("build", Some(matches)) => commands::images::build(&config, matches),
+            ("export", Some(matches)) => commands::images::export(&config, matches),
             ("import", Some(matches)) => commands::images::import(&config, matches),
             ("ls", Some(matches)) => commands::images::list(&config, matches),
             ("rm", Some(matches)) => commands::images::remove(&config, matches),
             _ => unimplemented!(),
         },
+        ("exec", Some(matches)) => commands::exec(&config, matches),
+        ("init", Some(matches)) => commands::init(matches),
         ("run", Some(matches)) => commands::run(&config, matches),
+        ("volume", Some(matches)) => match matches.subcommand() {
+            ("create", Some(matches)) => commands::volumes::create(&config, matches),
+            ("inspect", Some(matches)) => commands::volumes::inspect(&config, matches),
+            ("ls", Some(matches)) => commands::volumes::list(&config, matches),
+            ("rm", Some(matches)) => commands::volumes::remove(&config, matches),
+            _ => unimplemented!(),
+        },
         _ => unimplemented!(),
     };
 